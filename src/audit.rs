@@ -0,0 +1,105 @@
+use eyre::Result;
+use gateway_api::{gateways::Gateway, httproutes::HTTPRoute};
+use k8s_openapi::api::networking::v1::Ingress;
+use kube::{Client, Resource};
+use serde::Serialize;
+use serde_json::Value;
+use tracing::instrument;
+
+use crate::{
+    check::Manifest,
+    cli::{AuditArgs, Cli},
+    cluster::Cluster,
+    helpers::{SKIP_ANNOTATION, SKIP_REASON_ANNOTATION, SKIP_UNTIL_ANNOTATION},
+    report::{Finding, render},
+};
+
+impl Cli {
+    // Whether all the existing objects pass.
+    #[instrument(skip_all)]
+    pub async fn audit(&self, args: &AuditArgs) -> Result<bool> {
+        let cluster = Cluster::new(Client::try_default().await?).await?;
+        cluster.wait_until_synced().await?;
+
+        let mut manifests = vec![];
+        for object in cluster.list::<Ingress>(&args.namespace).await? {
+            manifests.push(to_manifest(&object)?);
+        }
+        for object in cluster.list::<Gateway>(&args.namespace).await? {
+            manifests.push(to_manifest(&object)?);
+        }
+        for object in cluster.list::<HTTPRoute>(&args.namespace).await? {
+            manifests.push(to_manifest(&object)?);
+        }
+        if args.include_skipped {
+            manifests.iter_mut().for_each(remove_skip);
+        }
+
+        let findings = self.evaluate(manifests, &cluster).await;
+        println!("{}", render(args.format, &findings)?);
+        Ok(!findings.iter().any(Finding::is_failure))
+    }
+}
+
+// Listed items do not always carry their type.
+pub fn to_manifest<K: Resource<DynamicType = ()> + Serialize>(object: &K) -> Result<Manifest> {
+    let mut object = serde_json::to_value(object)?;
+    object["apiVersion"] = K::api_version(&()).into();
+    object["kind"] = K::kind(&()).into();
+    Ok(Manifest {
+        source: None,
+        line: None,
+        object,
+    })
+}
+
+fn remove_skip(manifest: &mut Manifest) {
+    if let Some(annotations) = manifest
+        .object
+        .pointer_mut("/metadata/annotations")
+        .and_then(Value::as_object_mut)
+    {
+        for annotation in [
+            SKIP_ANNOTATION,
+            SKIP_UNTIL_ANNOTATION,
+            SKIP_REASON_ANNOTATION,
+        ] {
+            annotations.remove(annotation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{check::parse_manifests, cluster::ClusterObjects, report::Outcome};
+
+    const SKIPPED: &str = r#"
+apiVersion: networking.k8s.io/v1
+kind: Ingress
+metadata:
+  name: whoami
+  namespace: test
+  annotations:
+    ingress-tls.magiclouds.cn/skip: "true"
+    ingress-tls.magiclouds.cn/skip-reason: Testing
+spec:
+  rules:
+  - host: whoami.magicloud.lan
+"#;
+
+    #[actix_web::test]
+    async fn skipped_objects_are_included_on_request() {
+        let conf = Cli::parse_from(["ingress-tls", "check"]);
+        let cluster = Cluster::from_objects(ClusterObjects::default());
+        let mut manifests = parse_manifests("test.yaml", SKIPPED).unwrap();
+        let findings = conf.evaluate(manifests.clone(), &cluster).await;
+        assert_eq!(findings[0].outcome, Outcome::Passed);
+
+        manifests.iter_mut().for_each(remove_skip);
+        let findings = conf.evaluate(manifests, &cluster).await;
+        assert!(findings[0].is_failure());
+    }
+}
@@ -0,0 +1,438 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use eyre::{Result, eyre};
+use kube::core::GroupVersionKind;
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::instrument;
+
+#[allow(clippy::wildcard_imports)]
+use crate::{
+    cli::{CheckArgs, Cli, EnforcementMode},
+    cluster::{Cluster, ClusterObjects},
+    gateway::*,
+    helpers::*,
+    httproute::*,
+    ingress::*,
+    report::{Finding, Outcome, render},
+};
+
+// One document of the supplied manifests.
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    // File name, None for stdin or the cluster.
+    pub source: Option<String>,
+    // 1-based line of the document in the source, when known.
+    pub line: Option<usize>,
+    pub object: Value,
+}
+impl Manifest {
+    pub fn gvk(&self) -> GroupVersionKind {
+        let api_version = self.object["apiVersion"].as_str().unwrap_or_default();
+        let (group, version) = api_version.rsplit_once('/').unwrap_or(("", api_version));
+        let kind = self.object["kind"].as_str().unwrap_or_default();
+        GroupVersionKind::gvk(group, version, kind)
+    }
+
+    pub fn field(&self, pointer: &str) -> String {
+        self.object
+            .pointer(pointer)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+impl Cli {
+    // Whether all the supplied objects pass.
+    #[instrument(skip_all)]
+    pub async fn check(&self, args: &CheckArgs) -> Result<bool> {
+        let manifests = load_manifests(&args.paths)?;
+        let findings = self.check_manifests(manifests, &args.namespace).await;
+        println!("{}", render(args.format, &findings)?);
+        Ok(!findings.iter().any(Finding::is_failure))
+    }
+
+    pub async fn check_manifests(&self, manifests: Vec<Manifest>, namespace: &str) -> Vec<Finding> {
+        let mut manifests = manifests;
+        for manifest in &mut manifests {
+            if SupportedKind::from_gvk(&manifest.gvk()).is_some()
+                && manifest.object.pointer("/metadata/namespace").is_none()
+                && let Some(metadata) = manifest.object["metadata"].as_object_mut()
+            {
+                metadata.insert("namespace".to_string(), namespace.into());
+            }
+        }
+        let is = |kind| move |gvk: &GroupVersionKind| SupportedKind::from_gvk(gvk) == Some(kind);
+        let cluster = Cluster::from_objects(ClusterObjects {
+            namespaces: objects_of(&manifests, |gvk| {
+                *gvk == GroupVersionKind::gvk("", "v1", "Namespace")
+            }),
+            gateways: objects_of(&manifests, is(SupportedKind::Gateway)),
+            httproutes: objects_of(&manifests, is(SupportedKind::HTTPRoute)),
+            ingress_classes: objects_of(&manifests, |gvk| {
+                *gvk == GroupVersionKind::gvk("networking.k8s.io", "v1", "IngressClass")
+            }),
+            gateway_classes: objects_of(&manifests, |gvk| {
+                gvk.group == "gateway.networking.k8s.io" && gvk.kind == "GatewayClass"
+            }),
+        });
+        self.evaluate(manifests, &cluster).await
+    }
+
+    // Objects of unsupported kinds are ignored. The skip annotation is
+    // honored whoever set it.
+    pub async fn evaluate(&self, manifests: Vec<Manifest>, cluster: &Cluster) -> Vec<Finding> {
+        let mut findings = vec![];
+        for manifest in manifests {
+            let Some(kind) = SupportedKind::from_gvk(&manifest.gvk()) else {
+                continue;
+            };
+            let namespace = manifest.field("/metadata/namespace");
+            let requester = Requester {
+                resource: kind.resource().to_string(),
+                namespace: Some(namespace.clone()),
+                offline: true,
+                ..Default::default()
+            };
+            let status = self
+                .check_object(kind, &manifest, cluster, &requester)
+                .await;
+            let mode = resolve_enforcement_mode(self.enforcement_mode, &namespace, cluster);
+            findings.push(Finding {
+                source: manifest.source.clone(),
+                line: manifest.line,
+                api_version: manifest.field("/apiVersion"),
+                kind: manifest.field("/kind"),
+                name: manifest.field("/metadata/name"),
+                namespace,
+                outcome: match status {
+                    Ok(status) => outcome(status, mode),
+                    Err(e) => Outcome::Error(format!("{e}")),
+                },
+            });
+        }
+        findings
+    }
+
+    async fn check_object(
+        &self,
+        kind: SupportedKind,
+        manifest: &Manifest,
+        cluster: &Cluster,
+        requester: &Requester,
+    ) -> Result<Status> {
+        let object = manifest.object.clone();
+        Ok(match kind {
+            SupportedKind::Ingress => validate_ingress(self, cluster, requester)
+                .run(Arc::new(serde_json::from_value(object)?))
+                .await
+                .into(),
+            SupportedKind::Gateway => validate_gateway(self, cluster, requester)
+                .run(Arc::new(serde_json::from_value(object)?))
+                .await
+                .into(),
+            SupportedKind::HTTPRoute => validate_httproute(self, cluster, requester)
+                .run(Arc::new(serde_json::from_value(object)?))
+                .await
+                .into(),
+        })
+    }
+}
+
+fn outcome(status: Status, mode: EnforcementMode) -> Outcome {
+    match status {
+        Status::MoveOn | Status::Allowed | Status::Patch(_) => Outcome::Passed,
+        Status::Denied(DenyReason::InternalError(e)) => Outcome::Error(format!("{e}")),
+        Status::Denied(reason) if mode == EnforcementMode::Enforce => {
+            Outcome::Denied((&reason).into())
+        }
+        Status::Denied(reason) => Outcome::Warned((&reason).into()),
+        Status::Invalid(e) => Outcome::Error(e),
+    }
+}
+
+fn objects_of<K: for<'de> Deserialize<'de>>(
+    manifests: &[Manifest],
+    filter: impl Fn(&GroupVersionKind) -> bool,
+) -> Vec<K> {
+    manifests
+        .iter()
+        .filter(|m| filter(&m.gvk()))
+        .filter_map(|m| {
+            serde_json::from_value(m.object.clone())
+                .inspect_err(|e| {
+                    tracing::warn!(target: "check", message = format!("Ignoring an object in {}: {e}", m.source.as_deref().unwrap_or("stdin")));
+                })
+                .ok()
+        })
+        .collect()
+}
+
+#[instrument(skip_all)]
+pub fn load_manifests(paths: &[PathBuf]) -> Result<Vec<Manifest>> {
+    if paths.is_empty() {
+        return load_stdin();
+    }
+    let mut manifests = vec![];
+    for path in paths {
+        if path == Path::new("-") {
+            manifests.append(&mut load_stdin()?);
+        } else {
+            for file in manifest_files(path)? {
+                let text = std::fs::read_to_string(&file)
+                    .map_err(|e| eyre!("Cannot read {}: {e}", file.display()))?;
+                manifests.append(&mut parse_manifests(&file.display().to_string(), &text)?);
+            }
+        }
+    }
+    Ok(manifests)
+}
+
+fn load_stdin() -> Result<Vec<Manifest>> {
+    let mut text = String::new();
+    std::io::stdin().read_to_string(&mut text)?;
+    parse_manifests("-", &text)
+}
+
+// Files given explicitly are taken whatever their extension.
+fn manifest_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut entries = std::fs::read_dir(path)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    let mut files = vec![];
+    for entry in entries {
+        if entry.is_dir() {
+            files.append(&mut manifest_files(&entry)?);
+        } else if entry
+            .extension()
+            .and_then(|x| x.to_str())
+            .is_some_and(|x| ["yaml", "yml", "json"].contains(&x))
+        {
+            files.push(entry);
+        }
+    }
+    Ok(files)
+}
+
+// JSON is YAML. `List` objects, as `kubectl get -o yaml` outputs, are flattened.
+pub fn parse_manifests(source: &str, text: &str) -> Result<Vec<Manifest>> {
+    let documents = serde_yaml::Deserializer::from_str(text)
+        .map(|de| Value::deserialize(de).map_err(|e| eyre!("Cannot parse {source}: {e}")))
+        .collect::<Result<Vec<_>>>()?;
+    let lines = document_lines(text, documents.len());
+    let mut manifests = vec![];
+    for (object, line) in documents.into_iter().zip(lines) {
+        let objects = match object {
+            Value::Null => vec![],
+            Value::Object(ref o) if o.get("kind").is_some_and(|k| k == "List") => o
+                .get("items")
+                .and_then(Value::as_array)
+                .cloned()
+                .unwrap_or_default(),
+            object => vec![object],
+        };
+        // Items of a `List` share its line.
+        manifests.extend(objects.into_iter().map(|object| Manifest {
+            source: (source != "-").then(|| source.to_string()),
+            line,
+            object,
+        }));
+    }
+    Ok(manifests)
+}
+
+// The first content line of each of the `count` documents the YAML
+// deserializer yields. It yields one document per separator, plus one when
+// there is content before the first separator.
+fn document_lines(text: &str, count: usize) -> Vec<Option<usize>> {
+    let lines = text.lines().collect::<Vec<_>>();
+    let separators = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| {
+            l.strip_prefix("---")
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+        })
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    let starts = if count == separators.len() + 1 {
+        std::iter::once(0)
+            .chain(separators.iter().map(|s| s + 1))
+            .collect::<Vec<_>>()
+    } else if count == separators.len() {
+        separators.iter().map(|s| s + 1).collect()
+    } else {
+        return vec![None; count];
+    };
+    starts
+        .into_iter()
+        .map(|start| {
+            lines
+                .iter()
+                .enumerate()
+                .skip(start)
+                .take_while(|(i, _)| !separators.contains(i))
+                .find(|(_, l)| is_content(l))
+                .map(|(i, _)| i + 1)
+        })
+        .collect()
+}
+
+fn is_content(line: &str) -> bool {
+    let line = line.trim();
+    !line.is_empty() && !line.starts_with('#')
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    const MANIFESTS: &str = r#"
+apiVersion: gateway.networking.k8s.io/v1
+kind: Gateway
+metadata:
+  name: whoami
+spec:
+  gatewayClassName: traefik
+  listeners:
+  - name: http
+    port: 8000
+    protocol: HTTP
+  - name: https
+    port: 8443
+    protocol: HTTPS
+    tls:
+      certificateRefs:
+      - name: whoami
+---
+apiVersion: gateway.networking.k8s.io/v1
+kind: HTTPRoute
+metadata:
+  name: whoami
+spec:
+  parentRefs:
+  - kind: Gateway
+    name: whoami
+    sectionName: http
+  rules:
+  - backendRefs:
+    - name: whoami
+      port: 80
+---
+# Comments only
+---
+{"apiVersion": "v1", "kind": "List", "items": [
+  {"apiVersion": "networking.k8s.io/v1", "kind": "Ingress",
+   "metadata": {"name": "whoami", "namespace": "other"},
+   "spec": {"rules": [{"host": "whoami.magicloud.lan"}],
+            "tls": [{"hosts": ["whoami.magicloud.lan"]}]}},
+  {"apiVersion": "v1", "kind": "Service", "metadata": {"name": "whoami"}}
+]}
+"#;
+
+    fn conf(extra: &[&str]) -> Cli {
+        let args = [&["ingress-tls"], extra, &["check"]];
+        Cli::parse_from(args.concat())
+    }
+
+    #[test]
+    fn multi_document_stream() {
+        let manifests = parse_manifests("test.yaml", MANIFESTS).unwrap();
+        let kinds = manifests
+            .iter()
+            .map(|m| m.field("/kind"))
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, ["Gateway", "HTTPRoute", "Ingress", "Service"]);
+    }
+
+    #[actix_web::test]
+    async fn lookups_are_resolved_among_manifests() {
+        let manifests = parse_manifests("test.yaml", MANIFESTS).unwrap();
+        let findings = conf(&[]).check_manifests(manifests, "test").await;
+        let summary = findings
+            .iter()
+            .map(|f| {
+                (
+                    f.kind.as_str(),
+                    f.namespace.as_str(),
+                    matches!(f.outcome, Outcome::Denied(_)),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("Gateway", "test", true),
+                ("HTTPRoute", "test", true),
+                ("Ingress", "other", false)
+            ]
+        );
+        assert!(findings.iter().any(Finding::is_failure));
+    }
+
+    #[test]
+    fn documents_carry_their_line() {
+        let manifests = parse_manifests("test.yaml", MANIFESTS).unwrap();
+        let lines = manifests.iter().map(|m| m.line).collect::<Vec<_>>();
+        assert_eq!(lines, [Some(2), Some(19), Some(35), Some(35)]);
+        let manifests = parse_manifests("test.yaml", "---\nkind: A\n---\n\nkind: B\n").unwrap();
+        let lines = manifests.iter().map(|m| m.line).collect::<Vec<_>>();
+        assert_eq!(lines, [Some(2), Some(5)]);
+    }
+
+    #[actix_web::test]
+    async fn findings_name_offending_objects() {
+        let manifests = parse_manifests("test.yaml", MANIFESTS).unwrap();
+        let findings = conf(&[]).check_manifests(manifests, "test").await;
+        let related = |kind: &str| match &findings.iter().find(|f| f.kind == kind).unwrap().outcome
+        {
+            Outcome::Denied(v) => (
+                v.rule.clone(),
+                v.related
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+            ),
+            outcome => panic!("{outcome:?}"),
+        };
+        assert_eq!(
+            related("Gateway"),
+            (
+                "GatewayNonRedirectHTTPRouteAttachedToHTTPListener".to_string(),
+                vec!["HTTPRoute test/whoami listener http".to_string()]
+            )
+        );
+        assert_eq!(
+            related("HTTPRoute"),
+            (
+                "HTTPRouteNonRedirectAttachedToHTTPListener".to_string(),
+                vec!["Gateway test/whoami listener http".to_string()]
+            )
+        );
+    }
+
+    #[actix_web::test]
+    async fn warn_mode_does_not_fail() {
+        let manifests = parse_manifests("test.yaml", MANIFESTS).unwrap();
+        let findings = conf(&["--enforcement-mode", "warn"])
+            .check_manifests(manifests, "test")
+            .await;
+        assert!(findings.iter().all(|f| !f.is_failure()));
+        assert!(
+            findings
+                .iter()
+                .any(|f| matches!(f.outcome, Outcome::Warned(_)))
+        );
+    }
+}
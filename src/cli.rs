@@ -1,25 +1,35 @@
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
 use clap::{
-    Args, Parser,
+    Args, Parser, Subcommand, ValueEnum,
     builder::{StringValueParser, TypedValueParser},
 };
 use eyre::eyre;
+use serde::Deserialize;
 
-use crate::helpers::Issuer;
+use crate::{
+    helpers::{Issuer, SupportedKind},
+    policy::Policy,
+};
 
 #[derive(Parser, Clone, Debug)]
+#[command(subcommand_negates_reqs = true)]
 pub struct Cli {
+    /// Without a subcommand, serve the webhooks
+    #[command(subcommand)]
+    pub command: Option<Command>,
     /// Webhook service listening address
     /// In format `HOST:PORT`
     #[arg(short, long, default_value = "0.0.0.0:443",
-        value_parser = StringValueParser::new().try_map(|s| {
-            s.split_once(':').ok_or(eyre!("Invalid format of annotation"))
-            .and_then(|(a, b)| u16::from_str(b).map(|b|(a.to_owned(), b))
-            .map_err(|e| eyre!("{e:?}")))
-        }))]
+        value_parser = StringValueParser::new().try_map(|s| parse_address(&s)))]
     pub listen_address: (String, u16),
+    /// Plain HTTP listening address for `/healthz`, `/readyz` and `/metrics`
+    /// In format `HOST:PORT`
+    #[arg(long, default_value = "0.0.0.0:8080",
+        value_parser = StringValueParser::new().try_map(|s| parse_address(&s)))]
+    pub probe_listen_address: (String, u16),
     /// Cert Manager annotation to be added to the Ingress
     /// In format `NAME:VALUE`
     /// Required by mutating webhook.
@@ -31,31 +41,164 @@ pub struct Cli {
     #[arg(short, long)]
     pub traefik_ingress_redirect_resource_name: Option<String>,
     /// Webhook service TLS certificate files folder
-    #[arg(short('f'), long)]
-    pub tls_folder: PathBuf,
+    #[arg(short('f'), long, required = true)]
+    pub tls_folder: Option<PathBuf>,
     /// Webhook service TLS certificate file path
-    #[arg(short('c'), long)]
-    pub tls_certificate_file_name: String,
+    #[arg(short('c'), long, required = true)]
+    pub tls_certificate_file_name: Option<String>,
     /// Webhook service TLS private key file path
-    #[arg(short('k'), long)]
-    pub tls_private_key_file_name: String,
+    #[arg(short('k'), long, required = true)]
+    pub tls_private_key_file_name: Option<String>,
+    /// What to do with the object when the admission request cannot be
+    /// processed, such as a malformed review or an internal error
+    #[arg(long, value_enum, default_value_t = FailurePolicy::Closed)]
+    pub failure_policy: FailurePolicy,
+    /// Do not recognize cert-manager ACME HTTP-01 solver Ingress / `HTTPRoute`
+    /// objects, which are plain HTTP by design
+    #[arg(long, global = true)]
+    pub deny_acme_http01_solvers: bool,
+    /// What to do with objects failing the checks. Namespaces may override
+    /// this with the `ingress-tls.magiclouds.cn/enforce` label
+    #[arg(long, global = true, value_enum, default_value_t = EnforcementMode::Enforce)]
+    pub enforcement_mode: EnforcementMode,
+    /// Users allowed to use the `ingress-tls.magiclouds.cn/skip` annotation
+    #[arg(long, value_delimiter = ',')]
+    pub skip_allowed_users: Vec<String>,
+    /// Groups allowed to use the `ingress-tls.magiclouds.cn/skip` annotation
+    #[arg(long, value_delimiter = ',')]
+    pub skip_allowed_groups: Vec<String>,
+    /// Also allow the users granted the `skip` verb on the resource in the
+    /// `ingresstls.magiclouds.cn` API group, checked by `SubjectAccessReview`
+    #[arg(long)]
+    pub skip_subject_access_review: bool,
+    /// Longest exemption allowed through `ingress-tls.magiclouds.cn/skip-until`,
+    /// such as `30d`. The annotation is required when this is set
+    #[arg(long, global = true, value_parser = humantime::parse_duration)]
+    pub skip_max_duration: Option<Duration>,
+    /// Re-evaluate the existing objects at this interval, such as `1h`,
+    /// publishing Events and compliance gauges. Off when not set
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub audit_interval: Option<Duration>,
+    /// YAML, or TOML by the `.toml` extension, policy file. Its settings
+    /// override the flags, and it is reloaded when changed
+    #[arg(long, global = true)]
+    pub policy_file: Option<PathBuf>,
+    #[arg(skip)]
+    pub policy: Policy,
+}
+fn parse_address(s: &str) -> eyre::Result<(String, u16)> {
+    s.split_once(':')
+        .ok_or_else(|| eyre!("Invalid format of address"))
+        .and_then(|(a, b)| {
+            u16::from_str(b)
+                .map(|b| (a.to_owned(), b))
+                .map_err(|e| eyre!("{e:?}"))
+        })
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum Command {
+    /// Check manifests offline, such as in CI. Objects are looked up among the
+    /// manifests instead of the cluster
+    Check(CheckArgs),
+    /// Check the existing objects in the cluster, such as from a `CronJob`
+    Audit(AuditArgs),
+    /// Apply the mutations to the existing objects in the cluster
+    Fix(FixArgs),
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct CheckArgs {
+    /// YAML or JSON manifest files, or directories of them. `-` or nothing
+    /// for stdin
+    pub paths: Vec<PathBuf>,
+    /// Namespace of the objects which do not specify one
+    #[arg(short, long, default_value = "default")]
+    pub namespace: String,
+    /// Output format of the findings
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub format: ReportFormat,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct AuditArgs {
+    /// Namespaces to audit, all when not given
+    #[arg(short, long, value_delimiter = ',')]
+    pub namespace: Vec<String>,
+    /// Evaluate objects as if they had no skip annotation
+    #[arg(long)]
+    pub include_skipped: bool,
+    /// Output format of the findings
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub format: ReportFormat,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct FixArgs {
+    /// Namespaces to fix, all when not given
+    #[arg(short, long, value_delimiter = ',')]
+    pub namespace: Vec<String>,
+    /// Kinds to fix, all when not given
+    #[arg(short, long, value_enum, value_delimiter = ',')]
+    pub kind: Vec<SupportedKind>,
+    /// Print the changes as unified YAML diffs instead of applying them
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FailurePolicy {
+    /// Allow the object, with a warning
+    Open,
+    /// Deny the object
+    Closed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// Human readable lines
+    Text,
+    /// Versioned JSON document
+    Json,
+    /// SARIF 2.1.0, for code scanning
+    Sarif,
+    /// `JUnit` XML, one test case per object
+    Junit,
+    /// GitHub Actions workflow annotations
+    Github,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnforcementMode {
+    /// Deny the object
+    Enforce,
+    /// Allow the object, with the reason as a warning to the client
+    Warn,
+    /// Allow the object, with the reason in audit annotations and logs
+    Audit,
 }
 
-#[derive(Debug, Clone, Args)]
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
 pub struct CertManagerAnnotations {
-    #[arg(long, value_parser = StringValueParser::new().try_map(|s| {
-            s.split_once(':').ok_or(eyre!("Invalid format of annotation"))
-            .and_then(|(a, b)| if a.to_lowercase() == "namespaced" {
-                Ok(Issuer::Namespaced(b.to_owned()))
-            }else if a.to_lowercase() == "clustered" {
-                Ok(Issuer::Clustered(b.to_owned()))
-            } else {
-                Err(eyre!("Invalid issuer type"))
-            })
-        }))]
+    #[arg(long, value_parser = StringValueParser::new().try_map(|s| parse_issuer(&s)))]
     pub issuer: Issuer,
     #[arg(long)]
     pub kind: Option<String>,
     #[arg(long)]
     pub group: Option<String>,
 }
+
+pub fn parse_issuer(s: &str) -> eyre::Result<Issuer> {
+    s.split_once(':')
+        .ok_or_else(|| eyre!("Invalid format of annotation"))
+        .and_then(|(a, b)| {
+            if a.to_lowercase() == "namespaced" {
+                Ok(Issuer::Namespaced(b.to_owned()))
+            } else if a.to_lowercase() == "clustered" {
+                Ok(Issuer::Clustered(b.to_owned()))
+            } else {
+                Err(eyre!("Invalid issuer type"))
+            }
+        })
+}
@@ -0,0 +1,486 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Debug,
+    hash::Hash,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use async_compat::Compat;
+use eyre::{Result, eyre};
+use futures::{StreamExt, future};
+use gateway_api::{gatewayclasses::GatewayClass, gateways::Gateway, httproutes::HTTPRoute};
+use k8s_openapi::NamespaceResourceScope;
+use k8s_openapi::api::{
+    authentication::v1::UserInfo,
+    authorization::v1::{ResourceAttributes, SubjectAccessReview, SubjectAccessReviewSpec},
+    core::v1::Namespace,
+    networking::v1::IngressClass,
+};
+use kube::{
+    Api, Client, Resource,
+    api::{DynamicObject, ListParams, PostParams},
+    discovery::{self, Scope},
+    error::DiscoveryError,
+    runtime::{
+        WatchStreamExt,
+        reflector::{self, ObjectRef, Store},
+        watcher,
+    },
+};
+use serde::de::DeserializeOwned;
+use tracing::instrument;
+
+#[allow(clippy::wildcard_imports)]
+use crate::{cli::CertManagerAnnotations, helpers::*, metrics::KUBE_API_DURATION};
+
+type ObjectKey = (String, String);
+// Group, kind, namespace (empty for cluster issuers) and name.
+type IssuerKey = (String, String, String, String);
+
+// How long an issuer found to exist is trusted without asking again. The ones
+// not found are asked every time, so that creating them takes effect at once.
+const ISSUER_CACHE_TTL: Duration = Duration::from_mins(5);
+
+// The objects of a cluster made of fixtures, the kinds not given being empty.
+#[derive(Debug, Clone, Default)]
+pub struct ClusterObjects {
+    pub namespaces: Vec<Namespace>,
+    pub gateways: Vec<Gateway>,
+    pub httproutes: Vec<HTTPRoute>,
+    pub ingress_classes: Vec<IngressClass>,
+    pub gateway_classes: Vec<GatewayClass>,
+}
+
+// In-memory view of the cluster, maintained by watches in the background.
+// Admission requests only read from here, so that they do not hit the API
+// server.
+#[derive(Clone)]
+pub struct Cluster {
+    namespaces: Store<Namespace>,
+    gateways: Store<Gateway>,
+    httproutes: Store<HTTPRoute>,
+    ingress_classes: Store<IngressClass>,
+    gateway_classes: Store<GatewayClass>,
+    // Gateway -> HTTPRoutes referencing it as a parent.
+    parent_index: Arc<RwLock<ParentIndex>>,
+    // One per watch, set once its initial list is done.
+    synced: Vec<Arc<AtomicBool>>,
+    // Issuers found to exist, with when they were.
+    found_issuers: Arc<RwLock<HashMap<IssuerKey, Instant>>>,
+    // Found by discovery once, when the watches start.
+    gateway_api: bool,
+    // For the calls not served by the cache. None with fixture objects.
+    client: Option<Client>,
+}
+impl Cluster {
+    #[instrument(skip_all)]
+    pub async fn new(client: Client) -> Result<Self> {
+        let parent_index = Arc::new(RwLock::new(ParentIndex::default()));
+        let (namespaces, namespaces_synced) = reflect(Api::all(client.clone()), |_| {});
+        let (ingress_classes, ingress_classes_synced) = reflect(Api::all(client.clone()), |_| {});
+        let mut synced = vec![namespaces_synced, ingress_classes_synced];
+
+        // Clusters without Gateway API work with Ingress only.
+        let gateway_api =
+            is_served::<Gateway>(&client).await? && is_served::<HTTPRoute>(&client).await?;
+        let (gateways, httproutes, gateway_classes) = if gateway_api {
+            let (gateways, gateways_synced) = reflect(Api::all(client.clone()), |_| {});
+            let (gateway_classes, gateway_classes_synced) =
+                reflect(Api::all(client.clone()), |_| {});
+            let mut index_writer = ParentIndexWriter {
+                index: parent_index.clone(),
+                buffer: ParentIndex::default(),
+            };
+            let (httproutes, httproutes_synced) = reflect(Api::all(client.clone()), move |event| {
+                index_writer.apply(event);
+            });
+            synced.push(gateways_synced);
+            synced.push(httproutes_synced);
+            synced.push(gateway_classes_synced);
+            (gateways, httproutes, gateway_classes)
+        } else {
+            tracing::warn!(target: "cluster-cache", message = "Gateway API is not served, only Ingress is supported");
+            (store_of(vec![]), store_of(vec![]), store_of(vec![]))
+        };
+
+        Ok(Self {
+            namespaces,
+            gateways,
+            httproutes,
+            ingress_classes,
+            gateway_classes,
+            parent_index,
+            synced,
+            found_issuers: Arc::default(),
+            gateway_api,
+            client: Some(client),
+        })
+    }
+
+    // Lookups among given objects, such as manifests checked offline.
+    pub fn from_objects(objects: ClusterObjects) -> Self {
+        let mut index = ParentIndex::default();
+        for httproute in &objects.httproutes {
+            index.insert(httproute);
+        }
+        Self {
+            namespaces: store_of(objects.namespaces),
+            gateways: store_of(objects.gateways),
+            httproutes: store_of(objects.httproutes),
+            ingress_classes: store_of(objects.ingress_classes),
+            gateway_classes: store_of(objects.gateway_classes),
+            parent_index: Arc::new(RwLock::new(index)),
+            synced: vec![],
+            found_issuers: Arc::default(),
+            gateway_api: true,
+            client: None,
+        }
+    }
+
+    // By what discovery found when the watches started. Kinds other than
+    // Gateway API ones are core to Kubernetes.
+    pub fn serves<K: Resource<DynamicType = ()>>(&self) -> bool {
+        self.gateway_api || K::group(&()) != Gateway::group(&())
+    }
+
+    // Straight from the API server, all namespaces when none is given.
+    // Nothing when the kind is not served.
+    #[instrument(skip_all)]
+    pub async fn list<K>(&self, namespaces: &[String]) -> Result<Vec<K>>
+    where
+        K: Resource<DynamicType = (), Scope = NamespaceResourceScope>
+            + Clone
+            + DeserializeOwned
+            + Debug,
+    {
+        let client = self
+            .client
+            .clone()
+            .ok_or_else(|| eyre!("No access to the cluster"))?;
+        if !self.serves::<K>() {
+            return Ok(vec![]);
+        }
+        let apis = if namespaces.is_empty() {
+            vec![Api::all(client)]
+        } else {
+            namespaces
+                .iter()
+                .map(|ns| Api::namespaced(client.clone(), ns))
+                .collect()
+        };
+        let mut objects = vec![];
+        for api in apis {
+            let _timer = KUBE_API_DURATION.with_label_values(&["list"]).start_timer();
+            objects.extend(api.list(&ListParams::default()).await?.items);
+        }
+        Ok(objects)
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.synced.iter().all(|x| x.load(Ordering::Acquire))
+    }
+
+    #[instrument(skip_all)]
+    pub async fn wait_until_synced(&self) -> Result<()> {
+        self.namespaces.wait_until_ready().await?;
+        self.ingress_classes.wait_until_ready().await?;
+        self.gateways.wait_until_ready().await?;
+        self.httproutes.wait_until_ready().await?;
+        self.gateway_classes.wait_until_ready().await?;
+        tracing::info!(target: "cluster-cache", message = "Cluster cache synced");
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    pub async fn review_access(
+        &self,
+        user_info: &UserInfo,
+        resource_attributes: ResourceAttributes,
+    ) -> Result<bool> {
+        let client = self
+            .client
+            .clone()
+            .ok_or_else(|| eyre!("No access to the cluster"))?;
+        let _timer = KUBE_API_DURATION
+            .with_label_values(&["subject_access_review"])
+            .start_timer();
+        let review = SubjectAccessReview {
+            spec: SubjectAccessReviewSpec {
+                user: user_info.username.clone(),
+                groups: user_info.groups.clone(),
+                uid: user_info.uid.clone(),
+                extra: user_info.extra.clone(),
+                resource_attributes: Some(resource_attributes),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let review = Api::<SubjectAccessReview>::all(client)
+            .create(&PostParams::default(), &review)
+            .await?;
+        Ok(review.status.is_some_and(|s| s.allowed))
+    }
+
+    // The kind of the issuer is looked up by discovery, as external issuers,
+    // such as step-issuer, bring their own. Without access to the cluster,
+    // the issuer is assumed to exist. Found ones are cached for a while.
+    #[instrument(skip_all)]
+    pub async fn issuer_exists(
+        &self,
+        issuer: &CertManagerAnnotations,
+        namespace: &str,
+    ) -> Result<bool> {
+        let Some(client) = self.client.clone() else {
+            return Ok(true);
+        };
+        let group = issuer.group.as_deref().unwrap_or("cert-manager.io");
+        let kind = issuer.kind.as_deref().unwrap_or(match issuer.issuer {
+            Issuer::Namespaced(_) => "Issuer",
+            Issuer::Clustered(_) => "ClusterIssuer",
+        });
+        let key = (
+            group.to_string(),
+            kind.to_string(),
+            match issuer.issuer {
+                Issuer::Namespaced(_) => namespace.to_string(),
+                Issuer::Clustered(_) => String::new(),
+            },
+            issuer.issuer.name().to_string(),
+        );
+        if self
+            .found_issuers
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(&key)
+            .is_some_and(|x| x.elapsed() < ISSUER_CACHE_TTL)
+        {
+            return Ok(true);
+        }
+        let _timer = KUBE_API_DURATION
+            .with_label_values(&["issuer"])
+            .start_timer();
+        let group = match discovery::group(&client, group).await {
+            Ok(x) => x,
+            Err(kube::Error::Discovery(DiscoveryError::MissingApiGroup(_))) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let Some((resource, capabilities)) = group.recommended_kind(kind) else {
+            return Ok(false);
+        };
+        let api: Api<DynamicObject> = match (capabilities.scope, &issuer.issuer) {
+            (Scope::Namespaced, Issuer::Namespaced(_)) => {
+                Api::namespaced_with(client, namespace, &resource)
+            }
+            (Scope::Cluster, Issuer::Clustered(_)) => Api::all_with(client, &resource),
+            // Such as a namespaced kind given for a cluster issuer.
+            _ => return Ok(false),
+        };
+        let exists = api.get_opt(issuer.issuer.name()).await?.is_some();
+        if exists {
+            self.found_issuers
+                .write()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .insert(key, Instant::now());
+        }
+        Ok(exists)
+    }
+
+    pub fn get_gateway(&self, namespace: &str, name: &str) -> Option<Arc<Gateway>> {
+        self.gateways.get(&ObjectRef::new(name).within(namespace))
+    }
+
+    pub fn get_gateway_class(&self, name: &str) -> Option<Arc<GatewayClass>> {
+        self.gateway_classes.get(&ObjectRef::new(name))
+    }
+
+    pub fn get_httproutes_for_gateway(&self, namespace: &str, name: &str) -> Vec<Arc<HTTPRoute>> {
+        let index = self
+            .parent_index
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        index
+            .routes
+            .get(&(namespace.to_string(), name.to_string()))
+            .map(|routes| {
+                routes
+                    .iter()
+                    .filter_map(|(ns, n)| self.httproutes.get(&ObjectRef::new(n).within(ns)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn get_namespace(&self, name: &str) -> Option<Arc<Namespace>> {
+        self.namespaces.get(&ObjectRef::new(name))
+    }
+
+    pub fn filter_namespaces(&self, selectors: &[SelectorByLabel<'_, '_>]) -> Vec<String> {
+        self.namespaces
+            .state()
+            .into_iter()
+            .filter(|ns| {
+                let empty_btreemap = std::collections::BTreeMap::new();
+                let labels = ns.metadata.labels.as_ref().unwrap_or(&empty_btreemap);
+                selectors.iter().all(|s| s.matches(labels))
+            })
+            .filter_map(|ns| ns.metadata.name.clone())
+            .collect()
+    }
+
+    pub fn get_ingress_class(&self, name: Option<&str>) -> Option<Arc<IngressClass>> {
+        name.map_or_else(
+            || {
+                self.ingress_classes.find(|ic| {
+                    ic.metadata
+                        .annotations
+                        .as_ref()
+                        .and_then(|a| a.get(DEFAULT_INGRESS_CLASS_ANNOTATION))
+                        .is_some_and(|x| x == "true")
+                })
+            },
+            |name| self.ingress_classes.get(&ObjectRef::new(name)),
+        )
+    }
+}
+
+async fn is_served<K>(client: &Client) -> Result<bool>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
+    let api: Api<K> = Api::all(client.clone());
+    match api.list(&ListParams::default().limit(1)).await {
+        Ok(_) => Ok(true),
+        Err(kube::Error::Api(e)) if e.code == 404 => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn reflect<K>(
+    api: Api<K>,
+    mut on_event: impl FnMut(&watcher::Event<K>) + Send + 'static,
+) -> (Store<K>, Arc<AtomicBool>)
+where
+    K: Resource + Clone + DeserializeOwned + Debug + Send + Sync + 'static,
+    K::DynamicType: Default + Eq + Hash + Clone + Send + Sync,
+{
+    let (reader, writer) = reflector::store();
+    let synced = Arc::new(AtomicBool::new(false));
+    let s = synced.clone();
+    smol::spawn(Compat::new(
+        reflector::reflector(
+            writer,
+            watcher(api, watcher::Config::default()).default_backoff(),
+        )
+        .for_each(move |event| {
+            match event {
+                Ok(event) => {
+                    if matches!(event, watcher::Event::InitDone) {
+                        s.store(true, Ordering::Release);
+                    }
+                    on_event(&event);
+                }
+                Err(e) => {
+                    tracing::warn!(target: "cluster-cache", message = format!("{e:?}"));
+                }
+            }
+            future::ready(())
+        }),
+    ))
+    .detach();
+    (reader, synced)
+}
+
+fn store_of<K>(objects: Vec<K>) -> Store<K>
+where
+    K: Resource + Clone + 'static,
+    K::DynamicType: Default + Eq + Hash + Clone,
+{
+    let (reader, mut writer) = reflector::store();
+    writer.apply_watcher_event(&watcher::Event::Init);
+    for object in objects {
+        writer.apply_watcher_event(&watcher::Event::InitApply(object));
+    }
+    writer.apply_watcher_event(&watcher::Event::InitDone);
+    reader
+}
+
+#[derive(Debug, Default)]
+struct ParentIndex {
+    routes: HashMap<ObjectKey, BTreeSet<ObjectKey>>,
+    parents: HashMap<ObjectKey, Vec<ObjectKey>>,
+}
+impl ParentIndex {
+    fn insert(&mut self, httproute: &HTTPRoute) {
+        self.remove(httproute);
+        let Some(route) = object_key(httproute) else {
+            return;
+        };
+        let parents = httproute
+            .spec
+            .parent_refs
+            .iter()
+            .flatten()
+            .filter(|p| is_gateway_parentref(p))
+            .map(|p| {
+                (
+                    p.namespace.clone().unwrap_or_else(|| route.0.clone()),
+                    p.name.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        for parent in &parents {
+            self.routes
+                .entry(parent.clone())
+                .or_default()
+                .insert(route.clone());
+        }
+        self.parents.insert(route, parents);
+    }
+
+    fn remove(&mut self, httproute: &HTTPRoute) {
+        let Some(route) = object_key(httproute) else {
+            return;
+        };
+        for parent in self.parents.remove(&route).unwrap_or_default() {
+            if let Some(routes) = self.routes.get_mut(&parent) {
+                routes.remove(&route);
+                if routes.is_empty() {
+                    self.routes.remove(&parent);
+                }
+            }
+        }
+    }
+}
+
+// Follows the watcher events the same way the reflector store does.
+struct ParentIndexWriter {
+    index: Arc<RwLock<ParentIndex>>,
+    buffer: ParentIndex,
+}
+impl ParentIndexWriter {
+    fn apply(&mut self, event: &watcher::Event<HTTPRoute>) {
+        let index = || {
+            self.index
+                .write()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+        };
+        match event {
+            watcher::Event::Apply(httproute) => index().insert(httproute),
+            watcher::Event::Delete(httproute) => index().remove(httproute),
+            watcher::Event::Init => self.buffer = ParentIndex::default(),
+            watcher::Event::InitApply(httproute) => self.buffer.insert(httproute),
+            watcher::Event::InitDone => {
+                *index() = std::mem::take(&mut self.buffer);
+            }
+        }
+    }
+}
+
+fn object_key(o: &impl HasMetadata) -> Option<ObjectKey> {
+    let metadata = o.get_metadata();
+    Some((metadata.namespace.clone()?, metadata.name.clone()?))
+}
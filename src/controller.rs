@@ -0,0 +1,378 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{Debug, Display},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use eyre::{Report, Result, eyre};
+use futures::StreamExt;
+use gateway_api::{gateways::Gateway, httproutes::HTTPRoute};
+use k8s_openapi::{NamespaceResourceScope, api::networking::v1::Ingress};
+use kube::{
+    Api, Client, Resource, ResourceExt,
+    runtime::{
+        Controller,
+        controller::Action,
+        events::{Event, EventType, Recorder},
+        reflector::{ObjectRef, Store},
+        watcher,
+    },
+};
+use serde::{Serialize, de::DeserializeOwned};
+use tracing::instrument;
+
+use crate::{
+    audit::to_manifest,
+    cluster::Cluster,
+    lease::LeaderLease,
+    metrics,
+    policy::SharedConf,
+    report::{Finding, Outcome},
+};
+
+const AUDIT_LEASE: &str = "ingress-tls-audit";
+
+// Kind, namespace, name.
+type ObjectKey = (String, String, String);
+// (namespace, kind) -> (compliant, non-compliant)
+type Counts = BTreeMap<(String, String), (usize, usize)>;
+// Rule, message.
+type Decision = (String, String);
+
+// The controller wants a std error.
+#[derive(Debug)]
+struct ReconcileError(Report);
+impl Display for ReconcileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+impl std::error::Error for ReconcileError {}
+
+// Re-evaluates the existing objects periodically, so that drift, such as a
+// Gateway losing its HTTPS listener after its routes were admitted, is
+// reported without waiting for the next update.
+pub struct Auditor {
+    conf: SharedConf,
+    cluster: Cluster,
+    recorder: Recorder,
+    interval: Duration,
+    state: Mutex<ComplianceState>,
+}
+
+#[derive(Default)]
+struct ComplianceState {
+    compliant: BTreeMap<ObjectKey, bool>,
+    // Gauge labels published last time, to remove the ones gone.
+    published: BTreeSet<(String, String)>,
+    // Decisions an Event was published for, so that requeues do not repeat it.
+    reported: BTreeMap<ObjectKey, Decision>,
+}
+
+struct Context<K: Resource<DynamicType = ()> + Clone + 'static> {
+    auditor: Arc<Auditor>,
+    store: Store<K>,
+}
+
+impl Auditor {
+    pub fn new(conf: SharedConf, cluster: Cluster, client: Client, interval: Duration) -> Self {
+        Self {
+            conf,
+            cluster,
+            recorder: Recorder::new(client, env!("CARGO_PKG_NAME").into()),
+            interval,
+            state: Mutex::default(),
+        }
+    }
+
+    // Only the replica holding the lease audits, so that Events are not
+    // duplicated and the gauges are exported once.
+    #[instrument(skip_all)]
+    pub async fn run(self, client: Client) -> Result<()> {
+        self.cluster.wait_until_synced().await?;
+        // Clusters without Gateway API work with Ingress only.
+        let gateway_api = self.cluster.serves::<Gateway>();
+        let lease = LeaderLease::new(client.clone(), AUDIT_LEASE);
+        let auditor = Arc::new(self);
+        loop {
+            lease.acquire().await;
+            let mut controllers = vec![auditor.clone().control::<Ingress>(client.clone())];
+            if gateway_api {
+                controllers.push(auditor.clone().control::<Gateway>(client.clone()));
+                controllers.push(auditor.clone().control::<HTTPRoute>(client.clone()));
+            }
+            futures::future::select(
+                futures::future::join_all(controllers),
+                Box::pin(lease.keep()),
+            )
+            .await;
+            // The new leader exports them.
+            auditor.forget();
+        }
+    }
+
+    fn forget(&self) {
+        let state = std::mem::take(&mut *self.state.lock().expect("Compliance state poisoned"));
+        for (ns, kind) in &state.published {
+            metrics::remove_compliance(ns, kind);
+        }
+    }
+
+    fn control<K>(self: Arc<Self>, client: Client) -> futures::future::BoxFuture<'static, ()>
+    where
+        K: Resource<DynamicType = (), Scope = NamespaceResourceScope>
+            + Clone
+            + DeserializeOwned
+            + Serialize
+            + Debug
+            + Send
+            + Sync
+            + 'static,
+    {
+        let controller = Controller::new(Api::<K>::all(client), watcher::Config::default());
+        let context = Arc::new(Context {
+            auditor: self,
+            store: controller.store(),
+        });
+        Box::pin(
+            controller
+                .run(reconcile::<K>, error_policy::<K>, context)
+                .for_each(|result| async move {
+                    if let Err(e) = result {
+                        tracing::warn!(target: "audit-controller", message = format!("{e}"));
+                    }
+                }),
+        )
+    }
+
+    // Drops the objects gone from the store, then publishes the gauges.
+    fn publish<K>(&self, compliant: Option<(ObjectKey, bool)>, store: &Store<K>)
+    where
+        K: Resource<DynamicType = ()> + Clone + 'static,
+    {
+        let (counts, gone) = self
+            .state
+            .lock()
+            .expect("Compliance state poisoned")
+            .update(&K::kind(&()), compliant, |ns, name| {
+                store.get(&ObjectRef::new(name).within(ns)).is_some()
+            });
+        for ((ns, kind), (compliant, noncompliant)) in &counts {
+            metrics::set_compliance(ns, kind, *compliant, *noncompliant);
+        }
+        for (ns, kind) in &gone {
+            metrics::remove_compliance(ns, kind);
+        }
+    }
+}
+
+impl ComplianceState {
+    // Compliant and non-compliant counts by namespace and kind, and the
+    // namespace and kind pairs not having objects any more.
+    fn update(
+        &mut self,
+        kind: &str,
+        compliant: Option<(ObjectKey, bool)>,
+        exists: impl Fn(&str, &str) -> bool,
+    ) -> (Counts, Vec<(String, String)>) {
+        if let Some((key, compliant)) = compliant {
+            self.compliant.insert(key, compliant);
+        }
+        self.compliant
+            .retain(|(k, ns, name), _| k != kind || exists(ns, name));
+        self.reported
+            .retain(|(k, ns, name), _| k != kind || exists(ns, name));
+
+        let mut counts = Counts::new();
+        for ((k, ns, _), compliant) in &self.compliant {
+            let count = counts.entry((ns.clone(), k.clone())).or_default();
+            if *compliant {
+                count.0 += 1;
+            } else {
+                count.1 += 1;
+            }
+        }
+        let gone = self
+            .published
+            .iter()
+            .filter(|x| !counts.contains_key(*x))
+            .cloned()
+            .collect();
+        self.published = counts.keys().cloned().collect();
+        (counts, gone)
+    }
+
+    // Whether the decision differs from the one reported last for the object.
+    // Compliant objects are forgotten, to report them again once they are not.
+    fn is_new(&mut self, key: &ObjectKey, decision: Option<&Decision>) -> bool {
+        if decision.is_none() {
+            self.reported.remove(key);
+        }
+        decision.is_some_and(|x| self.reported.get(key) != Some(x))
+    }
+}
+
+async fn reconcile<K>(object: Arc<K>, context: Arc<Context<K>>) -> Result<Action, ReconcileError>
+where
+    K: Resource<DynamicType = ()> + Clone + Serialize + 'static,
+{
+    audit_object(object, context).await.map_err(ReconcileError)
+}
+
+#[instrument(skip_all, fields(name = object.name_any(), namespace = object.namespace()))]
+async fn audit_object<K>(object: Arc<K>, context: Arc<Context<K>>) -> Result<Action>
+where
+    K: Resource<DynamicType = ()> + Clone + Serialize + 'static,
+{
+    let auditor = &context.auditor;
+    let manifest = to_manifest(object.as_ref())?;
+    let finding = auditor
+        .conf
+        .current()
+        .await
+        .evaluate(vec![manifest], &auditor.cluster)
+        .await
+        .pop()
+        .ok_or_else(|| eyre!("Not a supported kind"))?;
+    let decision = match &finding.outcome {
+        Outcome::Passed => None,
+        Outcome::Denied(violation) | Outcome::Warned(violation) => {
+            Some((violation.rule.clone(), violation.message.clone()))
+        }
+        Outcome::Error(e) => return Err(eyre!("{e}")),
+    };
+    let key = key(&finding);
+    let is_new = auditor
+        .state
+        .lock()
+        .expect("Compliance state poisoned")
+        .is_new(&key, decision.as_ref());
+    if let Some((rule, message)) = decision.as_ref().filter(|_| is_new) {
+        auditor
+            .recorder
+            .publish(
+                &Event {
+                    type_: EventType::Warning,
+                    reason: rule.clone(),
+                    note: Some(truncate(message, 1024)),
+                    action: "Audit".to_string(),
+                    secondary: None,
+                },
+                &object.object_ref(&()),
+            )
+            .await?;
+        auditor
+            .state
+            .lock()
+            .expect("Compliance state poisoned")
+            .reported
+            .insert(key.clone(), (rule.clone(), message.clone()));
+    }
+    auditor.publish(Some((key, decision.is_none())), &context.store);
+    Ok(Action::requeue(auditor.interval))
+}
+
+// The signature is the one the controller wants.
+#[allow(clippy::needless_pass_by_value)]
+fn error_policy<K>(_object: Arc<K>, e: &ReconcileError, context: Arc<Context<K>>) -> Action
+where
+    K: Resource<DynamicType = ()> + Clone + 'static,
+{
+    tracing::warn!(target: "audit-controller", message = format!("{e}"));
+    context.auditor.publish(None, &context.store);
+    Action::requeue(context.auditor.interval)
+}
+
+fn key(finding: &Finding) -> ObjectKey {
+    (
+        finding.kind.clone(),
+        finding.namespace.clone(),
+        finding.name.clone(),
+    )
+}
+
+// Event notes are limited to 1kB.
+fn truncate(s: &str, max: usize) -> String {
+    if s.len() <= max {
+        return s.to_string();
+    }
+    let mut end = max - 3;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}...", &s[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(kind: &str, ns: &str, name: &str) -> ObjectKey {
+        (kind.to_string(), ns.to_string(), name.to_string())
+    }
+
+    #[test]
+    fn gauges_follow_objects() {
+        let mut state = ComplianceState::default();
+        state.update("Gateway", Some((key("Gateway", "a", "x"), true)), |_, _| {
+            true
+        });
+        state.update("Gateway", Some((key("Gateway", "b", "y"), true)), |_, _| {
+            true
+        });
+        let (counts, gone) = state.update(
+            "Gateway",
+            Some((key("Gateway", "a", "z"), false)),
+            |_, _| true,
+        );
+        assert_eq!(counts[&("a".to_string(), "Gateway".to_string())], (1, 1));
+        assert!(gone.is_empty());
+
+        // y is deleted, HTTPRoutes are not affected.
+        state.update(
+            "HTTPRoute",
+            Some((key("HTTPRoute", "b", "r"), false)),
+            |_, _| true,
+        );
+        let (counts, gone) = state.update("Gateway", None, |ns, _| ns != "b");
+        assert_eq!(
+            counts.keys().cloned().collect::<Vec<_>>(),
+            [
+                ("a".to_string(), "Gateway".to_string()),
+                ("b".to_string(), "HTTPRoute".to_string())
+            ]
+        );
+        assert_eq!(gone, [("b".to_string(), "Gateway".to_string())]);
+    }
+
+    #[test]
+    fn events_are_published_when_the_decision_changes() {
+        let mut state = ComplianceState::default();
+        let object = key("Gateway", "a", "x");
+        let decision = ("GatewayNoTLSListener".to_string(), "No TLS".to_string());
+        assert!(state.is_new(&object, Some(&decision)));
+        state.reported.insert(object.clone(), decision.clone());
+        assert!(!state.is_new(&object, Some(&decision)));
+
+        let other = ("IngressNoTLS".to_string(), "No TLS".to_string());
+        assert!(state.is_new(&object, Some(&other)));
+
+        // Fixed, then broken again.
+        assert!(!state.is_new(&object, None));
+        assert!(state.is_new(&object, Some(&decision)));
+
+        // Deleted.
+        state.reported.insert(object.clone(), decision.clone());
+        state.update("Gateway", None, |_, _| false);
+        assert!(state.is_new(&object, Some(&decision)));
+    }
+
+    #[test]
+    fn event_notes_are_truncated_on_char_boundaries() {
+        assert_eq!(truncate("short", 1024), "short");
+        let note = truncate(&"é".repeat(600), 1024);
+        assert!(note.len() <= 1024);
+        assert!(note.ends_with("..."));
+    }
+}
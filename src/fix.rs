@@ -0,0 +1,316 @@
+use std::{fmt::Debug, sync::Arc};
+
+use eyre::{Result, eyre};
+use gateway_api::{gateways::Gateway, httproutes::HTTPRoute};
+use json_patch::Patch;
+use k8s_openapi::{NamespaceResourceScope, api::networking::v1::Ingress};
+use kube::{
+    Api, Client, Resource, ResourceExt,
+    api::{Patch as KubePatch, PatchParams},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use similar::TextDiff;
+use tracing::instrument;
+
+#[allow(clippy::wildcard_imports)]
+use crate::{
+    audit::to_manifest,
+    check::Manifest,
+    cli::{Cli, FixArgs},
+    cluster::Cluster,
+    gateway::*,
+    helpers::*,
+    httproute::*,
+    ingress::*,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Remedy {
+    Compliant,
+    Fixed,
+    // Not enough information to make the mutation, with the original reason.
+    CannotInfer(String),
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct FixResult {
+    pub kind: String,
+    pub namespace: String,
+    pub name: String,
+    pub remedy: Remedy,
+}
+
+impl Cli {
+    // Whether all the selected objects are compliant or fixed.
+    #[instrument(skip_all)]
+    pub async fn fix(&self, args: &FixArgs) -> Result<bool> {
+        let client = Client::try_default().await?;
+        let cluster = Cluster::new(client.clone()).await?;
+        cluster.wait_until_synced().await?;
+
+        let kinds = if args.kind.is_empty() {
+            vec![
+                SupportedKind::Ingress,
+                SupportedKind::Gateway,
+                SupportedKind::HTTPRoute,
+            ]
+        } else {
+            args.kind.clone()
+        };
+        let mut results = vec![];
+        // Gateways go before HTTPRoutes, though the cache may not see the
+        // fixed Gateways yet.
+        for kind in kinds {
+            results.append(&mut match kind {
+                SupportedKind::Ingress => self.fix_kind::<Ingress>(&client, &cluster, args).await?,
+                SupportedKind::Gateway => self.fix_kind::<Gateway>(&client, &cluster, args).await?,
+                SupportedKind::HTTPRoute => {
+                    self.fix_kind::<HTTPRoute>(&client, &cluster, args).await?
+                }
+            });
+        }
+        println!("{}", summarize(&results, args.dry_run));
+        Ok(results
+            .iter()
+            .all(|x| matches!(x.remedy, Remedy::Compliant | Remedy::Fixed)))
+    }
+
+    async fn fix_kind<K>(
+        &self,
+        client: &Client,
+        cluster: &Cluster,
+        args: &FixArgs,
+    ) -> Result<Vec<FixResult>>
+    where
+        K: Resource<DynamicType = (), Scope = NamespaceResourceScope>
+            + Clone
+            + DeserializeOwned
+            + Serialize
+            + Debug,
+    {
+        let mut results = vec![];
+        for object in cluster.list::<K>(&args.namespace).await? {
+            let namespace = object.namespace().unwrap_or_default();
+            let name = object.name_any();
+            let manifest = to_manifest(&object)?;
+            let remedy = match self.remedy(&manifest, cluster).await {
+                Ok(Some(patch)) if args.dry_run => {
+                    println!("{}", diff(&manifest, &patch)?);
+                    Remedy::Fixed
+                }
+                Ok(Some(patch)) => {
+                    let api: Api<K> = Api::namespaced(client.clone(), &namespace);
+                    match apply(&api, &name, patch).await {
+                        Ok(()) => Remedy::Fixed,
+                        Err(e) => Remedy::Failed(format!("{e}")),
+                    }
+                }
+                Ok(None) => Remedy::Compliant,
+                Err(remedy) => remedy,
+            };
+            results.push(FixResult {
+                kind: K::kind(&()).to_string(),
+                namespace,
+                name,
+                remedy,
+            });
+        }
+        Ok(results)
+    }
+
+    // The patch to make, None when there is nothing to fix.
+    pub async fn remedy(
+        &self,
+        manifest: &Manifest,
+        cluster: &Cluster,
+    ) -> std::result::Result<Option<Patch>, Remedy> {
+        let object = manifest.object.clone();
+        let kind = SupportedKind::from_gvk(&manifest.gvk()).ok_or_else(|| {
+            Remedy::Failed(format!("{} is not supported", manifest.field("/kind")))
+        })?;
+        let requester = Requester {
+            resource: kind.resource().to_string(),
+            namespace: Some(manifest.field("/metadata/namespace")),
+            offline: true,
+            ..Default::default()
+        };
+        let failed = |e: serde_json::Error| Remedy::Failed(format!("{e}"));
+        let status: Status = match kind {
+            SupportedKind::Ingress => mutate_ingress(
+                Arc::new(serde_json::from_value(object).map_err(failed)?),
+                self,
+                cluster,
+                &requester,
+            )
+            .await
+            .into(),
+            SupportedKind::Gateway => mutate_gateway(
+                Arc::new(serde_json::from_value(object).map_err(failed)?),
+                self,
+                cluster,
+                &requester,
+            )
+            .await
+            .into(),
+            SupportedKind::HTTPRoute => mutate_httproute(
+                Arc::new(serde_json::from_value(object).map_err(failed)?),
+                self,
+                cluster,
+                &requester,
+            )
+            .await
+            .into(),
+        };
+        match status {
+            Status::MoveOn | Status::Allowed => Ok(None),
+            Status::Patch(patch) => Ok(Some(patch)),
+            Status::Denied(DenyReason::InternalError(e)) => Err(Remedy::Failed(format!("{e}"))),
+            // The mutators hand back the reason when they cannot make it.
+            Status::Denied(reason) => Err(Remedy::CannotInfer(reason.to_string())),
+            Status::Invalid(e) => Err(Remedy::CannotInfer(e)),
+        }
+    }
+}
+
+// The cluster checks the patch, against admission too, before it is applied.
+async fn apply<K>(api: &Api<K>, name: &str, patch: Patch) -> Result<()>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
+    let params = PatchParams {
+        field_manager: Some(env!("CARGO_PKG_NAME").to_string()),
+        ..Default::default()
+    };
+    let patch = KubePatch::Json::<()>(patch);
+    api.patch(
+        name,
+        &PatchParams {
+            dry_run: true,
+            ..params.clone()
+        },
+        &patch,
+    )
+    .await
+    .map_err(|e| eyre!("Server-side dry run failed: {e}"))?;
+    api.patch(name, &params, &patch).await?;
+    Ok(())
+}
+
+pub fn diff(manifest: &Manifest, patch: &Patch) -> Result<String> {
+    let mut target = manifest.object.clone();
+    json_patch::patch(&mut target, patch)?;
+    let before = serde_yaml::to_string(&manifest.object)?;
+    let after = serde_yaml::to_string(&target)?;
+    let name = format!(
+        "{}/{}/{}",
+        manifest.field("/kind"),
+        manifest.field("/metadata/namespace"),
+        manifest.field("/metadata/name")
+    );
+    Ok(TextDiff::from_lines(&before, &after)
+        .unified_diff()
+        .header(&format!("a/{name}"), &format!("b/{name}"))
+        .to_string())
+}
+
+fn summarize(results: &[FixResult], dry_run: bool) -> String {
+    let count = |f: fn(&Remedy) -> bool| results.iter().filter(|x| f(&x.remedy)).count();
+    let mut lines = vec![];
+    for x in results {
+        let (level, message) = match &x.remedy {
+            Remedy::Compliant | Remedy::Fixed => continue,
+            Remedy::CannotInfer(m) => (DenyReason::CannotInferenceMutation.name(), m),
+            Remedy::Failed(m) => ("error", m),
+        };
+        lines.push(format!(
+            "{level}: {} {}/{}: {message}",
+            x.kind, x.namespace, x.name
+        ));
+    }
+    lines.push(format!(
+        "{} objects checked, {} compliant, {} {}, {} could not be inferred, {} failed",
+        results.len(),
+        count(|x| *x == Remedy::Compliant),
+        count(|x| *x == Remedy::Fixed),
+        if dry_run { "to fix" } else { "fixed" },
+        count(|x| matches!(x, Remedy::CannotInfer(_))),
+        count(|x| matches!(x, Remedy::Failed(_))),
+    ));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{check::parse_manifests, cluster::ClusterObjects};
+
+    const MANIFESTS: &str = r"
+apiVersion: networking.k8s.io/v1
+kind: Ingress
+metadata:
+  name: whoami
+  namespace: test
+spec:
+  rules:
+  - host: whoami.magicloud.lan
+---
+apiVersion: gateway.networking.k8s.io/v1
+kind: Gateway
+metadata:
+  name: whoami
+  namespace: test
+spec:
+  gatewayClassName: traefik
+  listeners:
+  - name: http
+    port: 8000
+    protocol: HTTP
+  - name: https
+    hostname: whoami.magicloud.lan
+    port: 8443
+    protocol: HTTPS
+    tls:
+      certificateRefs:
+      - name: whoami
+---
+apiVersion: gateway.networking.k8s.io/v1
+kind: HTTPRoute
+metadata:
+  name: whoami
+  namespace: test
+spec:
+  parentRefs:
+  - kind: Gateway
+    name: whoami
+    sectionName: http
+  rules:
+  - backendRefs:
+    - name: whoami
+      port: 80
+";
+
+    #[actix_web::test]
+    async fn remedies_are_diffed_or_reported() {
+        let conf = Cli::parse_from(["ingress-tls", "--issuer", "clustered:letsencrypt", "fix"]);
+        let manifests = parse_manifests("test.yaml", MANIFESTS).unwrap();
+        let cluster = Cluster::from_objects(ClusterObjects {
+            gateways: vec![serde_json::from_value(manifests[1].object.clone()).unwrap()],
+            httproutes: vec![serde_json::from_value(manifests[2].object.clone()).unwrap()],
+            ..Default::default()
+        });
+
+        let patch = conf.remedy(&manifests[0], &cluster).await.unwrap().unwrap();
+        let diff = diff(&manifests[0], &patch).unwrap();
+        assert!(diff.starts_with("--- a/Ingress/test/whoami\n+++ b/Ingress/test/whoami\n"));
+        assert!(diff.contains("+    secretName: whoami-tls\n"));
+
+        // The HTTP listener has no hostname to convert it with.
+        assert!(matches!(
+            conf.remedy(&manifests[1], &cluster).await,
+            Err(Remedy::CannotInfer(_))
+        ));
+    }
+}
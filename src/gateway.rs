@@ -1,12 +1,9 @@
 use std::{collections::BTreeMap, sync::Arc};
 
 use eyre::Result;
-use futures::{StreamExt, stream};
 use gateway_api::{
     gateways::{
-        Gateway, GatewayListeners, GatewayListenersAllowedRoutes,
-        GatewayListenersAllowedRoutesNamespaces, GatewayListenersAllowedRoutesNamespacesFrom,
-        GatewayListenersTls, GatewayListenersTlsCertificateRefs, GatewayListenersTlsMode,
+        Gateway, GatewayListeners, GatewayListenersTls, GatewayListenersTlsCertificateRefs,
     },
     httproutes::HTTPRoute,
 };
@@ -14,40 +11,46 @@ use itertools::Itertools;
 use tracing::instrument;
 
 #[allow(clippy::wildcard_imports)]
-use crate::{cli::Cli, helpers::*};
+use crate::{
+    cli::{CertManagerAnnotations, Cli},
+    cluster::Cluster,
+    helpers::*,
+    naming::{LABEL_MAX, NameVariables, fit, short_hash},
+    policy::{GatewayClassPolicy, ListenerCertificates},
+};
 
 // This is not enough. One could have a full gateway but only http (non-redirect) route.
 // Once we have Gateway ready. Validate HTTPRoute.
 // httproute should be (parent)http -> redirect, (parent)https -> allow.
 // http one must only be redirect. So if no https route, accessing fails.
 #[instrument(skip_all)]
-pub fn validate_gateway<'a>() -> Checks<'a, Gateway, Option<Result<Status>>> {
+pub fn validate_gateway<'a>(
+    conf: &Cli,
+    cluster: &Cluster,
+    requester: &Requester,
+) -> Checks<'a, Gateway, Option<Result<Status>>> {
+    let allow_acme_http01_solvers = !conf.deny_acme_http01_solvers;
+    let cluster = cluster.clone();
     let x: Vec<AsyncClosure<'a, Gateway, Option<Result<Status>>>> = vec![
         // skip
-        Box::new(|gateway| {
-            Box::pin(async move {
-                let skip = get_skip(gateway.as_ref())?;
-                if skip == "true" {
-                    Some(Ok(Status::Allowed))
-                } else {
-                    Some(Ok(Status::MoveOn))
-                }
-            })
-        }),
+        skip_check(conf, &cluster, requester),
         // non-redirect HTTPRoutes attached
-        Box::new(|gateway| {
+        Box::new(move |gateway| {
+            let cluster = cluster.clone();
             Box::pin(async move {
-                let ret = get_bad_httproutes_for_gateway(&gateway).await?.map(|bad| {
-                    if bad.is_empty() {
-                        Status::MoveOn
-                    } else {
-                        Status::Denied(
-                            DenyReason::GatewayNonRedirectHTTPRouteAttachedToHTTPListener(
-                                bad.into_iter().map(|(l, v)| (l.clone(), v)).collect(),
-                            ),
-                        )
-                    }
-                });
+                let ret =
+                    get_bad_httproutes_for_gateway(&gateway, &cluster, allow_acme_http01_solvers)?
+                        .map(|bad| {
+                            if bad.is_empty() {
+                                Status::MoveOn
+                            } else {
+                                Status::Denied(
+                                    DenyReason::GatewayNonRedirectHTTPRouteAttachedToHTTPListener(
+                                        bad.into_iter().map(|(l, v)| (l.clone(), v)).collect(),
+                                    ),
+                                )
+                            }
+                        });
                 Some(ret)
             })
         }),
@@ -70,14 +73,16 @@ pub fn validate_gateway<'a>() -> Checks<'a, Gateway, Option<Result<Status>>> {
             })
         }),
     ];
-    x.into()
+    Checks::from(x).toggled(conf)
 }
 
 type ListenerHTTPRoutes<'a> = (&'a GatewayListeners, Parted<Vec<HTTPRoute>>);
 
 #[instrument(skip_all)]
-async fn get_bad_httproutes_for_gateway<'a>(
+fn get_bad_httproutes_for_gateway<'a>(
     gateway: &'a Gateway,
+    cluster: &Cluster,
+    allow_acme_http01_solvers: bool,
 ) -> Option<Result<Vec<ListenerHTTPRoutes<'a>>>> {
     let http_listeners = gateway
         .spec
@@ -87,17 +92,19 @@ async fn get_bad_httproutes_for_gateway<'a>(
     let gateway_name = gateway.metadata.name.as_ref()?;
     let gateway_namespace = gateway.metadata.namespace.as_ref()?;
 
-    let ret = stream::iter(http_listeners)
-        .filter_map(|listener| async move {
-            get_httproutes_for_listener(listener, gateway_name, gateway_namespace)
-                .await?
+    let ret = http_listeners
+        .filter_map(|listener| {
+            get_httproutes_for_listener(listener, gateway_name, gateway_namespace, cluster)?
                 .map(|httproutes| {
                     tracing::debug!(
                         "{} HTTPRoute-s are attached to this listener",
                         httproutes.len()
                     );
                     let parted: (Vec<HTTPRoute>, Vec<HTTPRoute>) =
-                        httproutes.into_iter().partition(is_redirect_or_no_rule);
+                        httproutes.into_iter().partition(|x| {
+                            is_redirect_or_no_rule(x)
+                                || (allow_acme_http01_solvers && is_acme_http01_solver_httproute(x))
+                        });
                     if parted.1.is_empty() {
                         None
                     } else {
@@ -112,85 +119,51 @@ async fn get_bad_httproutes_for_gateway<'a>(
                 })
                 .transpose()
         })
-        .collect::<Vec<_>>()
-        .await
-        .into_iter()
         .collect::<Result<Vec<_>>>();
 
     Some(ret)
 }
 
 #[instrument(skip_all)]
-async fn get_httproutes_for_listener(
+fn get_httproutes_for_listener(
     listener: &GatewayListeners,
     gateway_name: &str,
     gateway_namespace: &str,
+    cluster: &Cluster,
 ) -> Option<Result<Vec<HTTPRoute>>> {
+    if !does_listener_allow_httproute_kind(listener) {
+        return Some(Ok(vec![]));
+    }
+    let namespaces = get_allowed_namespaces(listener, gateway_namespace, cluster);
     let try_closure = || {
-        let x = listener.allowed_routes.as_ref()?.namespaces.as_ref()?;
-        Some(x)
-    };
-    let ns_sel = try_closure().unwrap_or(&GatewayListenersAllowedRoutesNamespaces {
-        from: Some(GatewayListenersAllowedRoutesNamespacesFrom::Same),
-        selector: None,
-    });
-    let namespaces: Result<Namespaces<'_>> = match ns_sel.from {
-        Some(GatewayListenersAllowedRoutesNamespacesFrom::All) => Ok(Namespaces::All),
-        Some(GatewayListenersAllowedRoutesNamespacesFrom::Selector) => {
-            let s = ns_sel.selector.as_ref()?;
-            let try_closure = || async {
-                let m = s.match_expressions.as_ref().map_or(Ok(vec![]), |x| {
-                    x.iter()
-                        .map(|x| x.clone().try_into())
-                        .collect::<Result<Vec<_>>>()
-                })?;
-                let empty_btreemap = BTreeMap::new();
-                let mut l = s
-                    .match_labels
-                    .as_ref()
-                    .unwrap_or(&empty_btreemap)
-                    .iter()
-                    .map(std::convert::Into::into)
-                    .collect::<Vec<_>>();
-                let mut selectors = m;
-                selectors.append(&mut l);
-                let nss = filter_namespaces(&selectors).await?;
-                Ok(Namespaces::Some(
-                    nss.into_iter().map(std::convert::Into::into).collect(),
-                ))
-            };
-            try_closure().await
-        }
-        Some(GatewayListenersAllowedRoutesNamespacesFrom::Same) | None => {
-            Ok(Namespaces::Some(vec![gateway_namespace.into()]))
-        }
-    };
-    let try_closure = || async {
         let namespaces = namespaces?;
         tracing::debug!("{namespaces:?}");
         // Get HTTPRoutes that parentRef to this Gateway
-        let httproutes = get_httproutes(&namespaces).await?;
+        let httproutes = cluster.get_httproutes_for_gateway(gateway_namespace, gateway_name);
         tracing::debug!("Totally {} HTTPRoutes found", httproutes.len());
         let x: Vec<HTTPRoute> = httproutes
             .into_iter()
             .filter_map(|httproute| {
                 let hns = httproute.metadata.namespace.as_ref()?;
-                if httproute
-                    .spec
-                    .parent_refs
-                    .as_ref()?
-                    .iter()
-                    .all(|parentref| {
-                        does_parentref_listener_match(
-                            parentref,
-                            listener,
-                            gateway_name,
-                            gateway_namespace,
-                            hns,
-                        )
-                    })
+                // Attached when any of its parentRefs points to the listener,
+                // such as a route of both the HTTP and HTTPS listeners.
+                if namespaces.contains(hns)
+                    && httproute
+                        .spec
+                        .parent_refs
+                        .as_ref()?
+                        .iter()
+                        .any(|parentref| {
+                            does_parentref_listener_match(
+                                parentref,
+                                listener,
+                                gateway_name,
+                                gateway_namespace,
+                                hns,
+                            )
+                        })
                 {
-                    Some(httproute)
+                    Some((*httproute).clone())
                 } else {
                     None
                 }
@@ -200,8 +173,7 @@ async fn get_httproutes_for_listener(
         Ok(x)
     };
 
-    let x = try_closure().await;
-    Some(x)
+    Some(try_closure())
 }
 
 // Gateway: Add HTTPS protocol listener. Need hostname and port.
@@ -209,90 +181,190 @@ async fn get_httproutes_for_listener(
 // There would be two issues.
 // 1. There are already HTTPS listeners.
 // 2. There are redirect as well.
-// If http listener is 80(8000), assume https is 443(8443), otherwise fails.
+// HTTPS port is 443, unless the policy sets another for the GatewayClass,
+// such as 8443 for Traefik. So do the TLS mode, the allowedRoutes and the
+// other fields of the listeners.
 // When a non-redirect http route comes in, turn it into https section.
 // If there is no redirect http route after all, not so bad.
 // hostname and port are logically impossible to get.
 // Guess hostname from ExternalDNS annotation. Or from http listener.
 #[instrument(skip_all)]
-pub async fn mutate_gateway(gateway: Arc<Gateway>, conf: &Cli) -> Option<Result<Status>> {
-    let validate_result = validate_gateway().run(gateway.clone()).await?;
+pub async fn mutate_gateway(
+    gateway: Arc<Gateway>,
+    conf: &Cli,
+    cluster: &Cluster,
+    requester: &Requester,
+) -> Option<Result<Status>> {
+    let validate_result = validate_gateway(conf, cluster, requester)
+        .run(gateway.clone())
+        .await?;
     match validate_result {
         Ok(Status::Denied(DenyReason::GatewayNoTLSListener)) => {
-            mutate_gateway_add_listeners(gateway.as_ref(), conf)
+            mutate_gateway_add_listeners(gateway.as_ref(), conf, cluster).await
         }
         Ok(Status::Denied(DenyReason::GatewayNonRedirectHTTPRouteAttachedToHTTPListener(
             listener_parted_routes,
-        ))) => mutate_gateway_convert_listeners(listener_parted_routes, gateway.as_ref()),
+        ))) => mutate_gateway_convert_listeners(
+            listener_parted_routes,
+            gateway.as_ref(),
+            conf,
+            cluster,
+        ),
         _ => Some(validate_result),
     }
 }
 
 #[instrument(skip_all)]
-fn mutate_gateway_add_listeners(gateway: &Gateway, conf: &Cli) -> Option<Result<Status>> {
-    let port = if gateway.spec.gateway_class_name == "traefik" {
-        8443
-    } else {
-        443
-    };
+async fn mutate_gateway_add_listeners(
+    gateway: &Gateway,
+    conf: &Cli,
+    cluster: &Cluster,
+) -> Option<Result<Status>> {
+    let class = gateway_class_policy(gateway, conf, cluster);
 
     let mut target = (*gateway).clone();
 
-    let edns_hostnames = get_external_dns_hostname(gateway).unwrap_or_default();
-    let mut hostnames = gateway
-        .spec
-        .listeners
-        .iter()
-        .filter_map(|x| x.hostname.as_ref())
-        .collect::<Vec<_>>();
-    hostnames.extend(edns_hostnames.iter());
-    let hostnames = hostnames.into_iter().unique();
+    let hostnames = get_hostnames_to_secure(gateway);
+    let issuer = match conf
+        .resolve_issuer(&gateway.metadata, &hostnames, cluster)
+        .await
+    {
+        Ok(x) => x,
+        Err(reason) => return Some(Ok(Status::Denied(reason))),
+    };
 
     // Running to this reason, means there is no non-redirect httproute attached.
     // How to guarantee?
     let gn = gateway.metadata.name.as_ref()?;
     let gns = gateway.metadata.namespace.as_ref()?;
-    for hostname in hostnames {
-        target.spec.listeners.push(GatewayListeners {
-            allowed_routes: Some(GatewayListenersAllowedRoutes {
-                kinds: None,
-                namespaces: Some(GatewayListenersAllowedRoutesNamespaces {
-                    from: Some(GatewayListenersAllowedRoutesNamespacesFrom::Same),
-                    selector: None,
-                }),
-            }),
+    let templates = &conf.policy.templates;
+    let variables = |hostname| NameVariables {
+        namespace: gns,
+        name: gn,
+        listener: "",
+        host: hostname,
+    };
+    let listener_names = hostnames
+        .iter()
+        .map(|x| templates.gateway_listener(&variables(x)))
+        .collect::<Vec<_>>();
+    let unique_listener_names = make_listener_names_unique(gateway, &hostnames, &listener_names);
+    for ((hostname, listener_name), unique_listener_name) in hostnames
+        .iter()
+        .zip(&listener_names)
+        .zip(unique_listener_names)
+    {
+        // A shared secret is named after the listener name from the template,
+        // which is the same for all hostnames unless it refers to them.
+        let secret_name = templates.gateway_listener_secret(&NameVariables {
+            listener: match conf.policy.listener_certificates {
+                ListenerCertificates::Shared => listener_name,
+                ListenerCertificates::Own => &unique_listener_name,
+            },
+            ..variables(hostname)
+        });
+        let listener = GatewayListeners {
+            allowed_routes: Some(class.allowed_routes()),
             hostname: Some(hostname.clone()),
-            name: format!("{gn}-https"),
-            port,
+            name: unique_listener_name,
+            port: class.https_port(),
             protocol: "HTTPS".to_string(),
             tls: Some(GatewayListenersTls {
                 certificate_refs: Some(vec![GatewayListenersTlsCertificateRefs {
                     group: None,
                     kind: None,
-                    name: format!("{gn}-https-tls"),
+                    name: secret_name,
                     namespace: Some(gns.clone()),
                 }]),
-                mode: Some(GatewayListenersTlsMode::Terminate),
+                mode: Some(class.tls_mode()),
                 options: None,
             }),
-        });
+        };
+        match class.apply_listener_fields(&listener) {
+            Ok(x) => target.spec.listeners.push(x),
+            Err(e) => return Some(Err(e)),
+        }
     }
     let mut annotations = target.metadata.annotations.take().unwrap_or_default();
-    patch_annotations(&mut annotations, conf);
+    patch_annotations(&mut annotations, issuer.as_ref());
     target.metadata.annotations = Some(annotations);
     Some(patch(gateway, &target).map(Status::Patch))
 }
 
+// The policy of the GatewayClass of the Gateway, by its name or its
+// controller.
+fn gateway_class_policy(gateway: &Gateway, conf: &Cli, cluster: &Cluster) -> GatewayClassPolicy {
+    let class_name = &gateway.spec.gateway_class_name;
+    let controller_name = cluster
+        .get_gateway_class(class_name)
+        .map(|x| x.spec.controller_name.clone());
+    conf.policy
+        .gateway_class(class_name, controller_name.as_deref())
+}
+
+// Hostnames of the listeners and the external-dns annotation, normalized,
+// leaving out the ones an HTTPS listener already covers.
+fn get_hostnames_to_secure(gateway: &Gateway) -> Vec<String> {
+    let https_listeners = gateway
+        .spec
+        .listeners
+        .iter()
+        .filter(|l| l.protocol == "HTTPS")
+        .collect::<Vec<_>>();
+    gateway
+        .spec
+        .listeners
+        .iter()
+        .filter_map(|x| x.hostname.clone())
+        .chain(get_external_dns_hostname(gateway).unwrap_or_default())
+        .map(|x| normalize_hostname(&x))
+        .filter(|x| !x.is_empty())
+        .unique()
+        .filter(|x| {
+            !https_listeners.iter().any(|l| {
+                l.hostname
+                    .as_ref()
+                    .is_none_or(|pattern| does_hostname_cover(pattern, x))
+            })
+        })
+        .collect()
+}
+
+// Listener names have to be unique in a Gateway. When the template gives the
+// same name to several hostnames, or one already taken, the hash of the
+// hostname is appended, which keeps the names stable across mutations.
+fn make_listener_names_unique(
+    gateway: &Gateway,
+    hostnames: &[String],
+    names: &[String],
+) -> Vec<String> {
+    let taken = gateway
+        .spec
+        .listeners
+        .iter()
+        .map(|l| l.name.as_str())
+        .collect::<Vec<_>>();
+    names
+        .iter()
+        .zip(hostnames)
+        .map(|(name, hostname)| {
+            if taken.contains(&name.as_str()) || names.iter().filter(|x| *x == name).count() > 1 {
+                fit(&format!("{name}-{}", short_hash(hostname)), LABEL_MAX)
+            } else {
+                name.clone()
+            }
+        })
+        .collect()
+}
+
 #[instrument(skip_all)]
 fn mutate_gateway_convert_listeners(
     listener_parted_routes: Vec<(GatewayListeners, Parted<Vec<HTTPRoute>>)>,
     gateway: &Gateway,
+    conf: &Cli,
+    cluster: &Cluster,
 ) -> Option<Result<Status>> {
-    let port = if gateway.spec.gateway_class_name == "traefik" {
-        8443
-    } else {
-        443
-    };
+    let class = gateway_class_policy(gateway, conf, cluster);
 
     let mut target = (*gateway).clone();
     // The HTTP listener is used by both non-redirect and regular
@@ -312,22 +384,34 @@ fn mutate_gateway_convert_listeners(
     let gns = gateway.metadata.namespace.as_ref()?;
     if inconvertible_listeners.is_empty() {
         for (li, _) in convertible_listeners {
-            target.spec.listeners.iter_mut().for_each(|l| {
+            for l in &mut target.spec.listeners {
                 if l.name == li.name {
                     l.protocol = "HTTPS".to_string();
                     l.tls = Some(GatewayListenersTls {
                         certificate_refs: Some(vec![GatewayListenersTlsCertificateRefs {
                             group: None,
                             kind: None,
-                            name: format!("{gn}-{}-tls", l.name),
+                            name: conf
+                                .policy
+                                .templates
+                                .converted_listener_secret(&NameVariables {
+                                    namespace: gns,
+                                    name: gn,
+                                    listener: &l.name,
+                                    host: l.hostname.as_deref().unwrap_or_default(),
+                                }),
                             namespace: Some(gns.clone()),
                         }]),
-                        mode: Some(GatewayListenersTlsMode::Terminate),
+                        mode: Some(class.tls_mode()),
                         options: None,
                     });
-                    l.port = port;
+                    l.port = class.converted_port(l.port);
+                    match class.apply_listener_fields(l) {
+                        Ok(x) => *l = x,
+                        Err(e) => return Some(Err(e)),
+                    }
                 }
-            });
+            }
         }
         Some(patch(gateway, &target).map(Status::Patch))
     } else {
@@ -338,8 +422,11 @@ fn mutate_gateway_convert_listeners(
 }
 
 #[instrument(skip_all)]
-fn patch_annotations(annotations: &mut BTreeMap<String, String>, conf: &Cli) {
-    if let Some(ref x) = conf.cma {
+fn patch_annotations(
+    annotations: &mut BTreeMap<String, String>,
+    cma: Option<&CertManagerAnnotations>,
+) {
+    if let Some(x) = cma {
         if let Some(ref group) = x.group {
             annotations
                 .entry(ISSUER_GROUP.to_string())
@@ -364,3 +451,260 @@ fn patch_annotations(annotations: &mut BTreeMap<String, String>, conf: &Cli) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::{
+        cluster::ClusterObjects,
+        testing::{apply, call, call_in, gateway, plain_httproute, policy_file, review},
+    };
+
+    #[test]
+    fn hostnames_covered_by_https_listeners_are_left_out() {
+        let gateway: Gateway = serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "name": "whoami",
+                "annotations": {
+                    "external-dns.alpha.kubernetes.io/hostname":
+                        "a.magicloud.lan.,b.magicloud.lan,c.magicloud.lan,.magicloud.lan"
+                }
+            },
+            "spec": {
+                "gatewayClassName": "traefik",
+                "listeners": [
+                    {"name": "http", "port": 8000, "protocol": "HTTP", "hostname": "b.magicloud.lan"},
+                    {
+                        "name": "a",
+                        "port": 8443,
+                        "protocol": "HTTPS",
+                        "hostname": "a.magicloud.lan",
+                        "tls": {"certificateRefs": [{"name": "a"}]}
+                    },
+                    {"name": "https", "port": 8443, "protocol": "HTTP"}
+                ]
+            }
+        }))
+        .unwrap();
+        assert_eq!(
+            get_hostnames_to_secure(&gateway),
+            ["b.magicloud.lan", "c.magicloud.lan", "*.magicloud.lan"]
+        );
+
+        // The template name is taken by the HTTP listener named `https`.
+        let names = make_listener_names_unique(
+            &gateway,
+            &["b.magicloud.lan".to_string()],
+            &["https".to_string()],
+        );
+        assert_ne!(names, ["https"]);
+        assert!(names[0].starts_with("https-"));
+    }
+
+    #[test]
+    fn httproutes_attach_by_any_parentref() {
+        let gateway = |name: &str| -> Gateway {
+            serde_json::from_value(serde_json::json!({
+                "metadata": {"name": name, "namespace": "test"},
+                "spec": {
+                    "gatewayClassName": "traefik",
+                    "listeners": [
+                        {"name": "http", "port": 8000, "protocol": "HTTP"},
+                        {"name": "https", "port": 8443, "protocol": "HTTPS"}
+                    ]
+                }
+            }))
+            .unwrap()
+        };
+        let httproute = |name: &str, parent_refs: serde_json::Value| -> HTTPRoute {
+            serde_json::from_value(serde_json::json!({
+                "metadata": {"name": name, "namespace": "test"},
+                "spec": {"parentRefs": parent_refs}
+            }))
+            .unwrap()
+        };
+        let cluster = Cluster::from_objects(ClusterObjects {
+            gateways: vec![gateway("whoami"), gateway("other")],
+            httproutes: vec![
+                httproute(
+                    "both-listeners",
+                    serde_json::json!([
+                        {"name": "whoami", "sectionName": "https"},
+                        {"name": "whoami", "sectionName": "http"}
+                    ]),
+                ),
+                httproute(
+                    "both-gateways",
+                    serde_json::json!([
+                        {"name": "other", "sectionName": "http"},
+                        {"name": "whoami", "sectionName": "http"}
+                    ]),
+                ),
+                httproute("no-kind", serde_json::json!([{"name": "whoami"}])),
+                httproute(
+                    "by-port",
+                    serde_json::json!([{"kind": "Gateway", "name": "whoami", "port": 8000}]),
+                ),
+                httproute(
+                    "https-only",
+                    serde_json::json!([{"name": "whoami", "sectionName": "https"}]),
+                ),
+                httproute(
+                    "other-port",
+                    serde_json::json!([{"name": "whoami", "port": 8443}]),
+                ),
+                httproute(
+                    "other-group",
+                    serde_json::json!([{"group": "networking.istio.io", "name": "whoami"}]),
+                ),
+            ],
+            ..Default::default()
+        });
+        let gateway = gateway("whoami");
+        let names =
+            get_httproutes_for_listener(&gateway.spec.listeners[0], "whoami", "test", &cluster)
+                .unwrap()
+                .unwrap()
+                .into_iter()
+                .filter_map(|x| x.metadata.name)
+                .sorted()
+                .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["both-gateways", "both-listeners", "by-port", "no-kind"]
+        );
+    }
+
+    // After the first one.
+    async fn added_listeners(object: &Value, extra: &[&str]) -> Vec<Value> {
+        let ret = call("/mutate", &review(object), extra).await;
+        apply(object, &ret["response"])["spec"]["listeners"]
+            .as_array()
+            .unwrap()[1..]
+            .to_vec()
+    }
+
+    #[actix_web::test]
+    async fn gateway_gets_a_listener_per_hostname() {
+        let object = json!({
+            "apiVersion": "gateway.networking.k8s.io/v1",
+            "kind": "Gateway",
+            "metadata": {
+                "name": "whoami",
+                "namespace": "test",
+                "annotations": {
+                    "external-dns.alpha.kubernetes.io/hostname":
+                        "whoami.magicloud.lan.,Whoami2.magicloud.lan,whoami.magicloud.lan"
+                }
+            },
+            "spec": {
+                "gatewayClassName": "traefik",
+                "listeners": [{"name": "http", "port": 8000, "protocol": "HTTP"}]
+            }
+        });
+        let added = added_listeners(&object, &[]).await;
+        assert_eq!(added.len(), 2);
+        assert_eq!(added[0]["hostname"], "whoami.magicloud.lan");
+        assert_eq!(added[1]["hostname"], "whoami2.magicloud.lan");
+        assert_ne!(added[0]["name"], added[1]["name"]);
+        assert!(
+            added[0]["name"]
+                .as_str()
+                .unwrap()
+                .starts_with("whoami-https-")
+        );
+        // Stable across mutations.
+        assert_eq!(added, added_listeners(&object, &[]).await);
+        for x in &added {
+            assert_eq!(x["port"], 8443);
+            assert_eq!(x["tls"]["certificateRefs"][0]["name"], "whoami-https-tls");
+        }
+
+        let file = policy_file("listenerCertificates: own");
+        let added =
+            added_listeners(&object, &["--policy-file", file.path().to_str().unwrap()]).await;
+        for x in &added {
+            assert_eq!(
+                x["tls"]["certificateRefs"][0]["name"],
+                format!("{}-tls", x["name"].as_str().unwrap())
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn gateway_class_is_configured_by_its_controller() {
+        let object = json!({
+            "apiVersion": "gateway.networking.k8s.io/v1",
+            "kind": "Gateway",
+            "metadata": {
+                "name": "whoami",
+                "namespace": "test",
+                "annotations": {"external-dns.alpha.kubernetes.io/hostname": "whoami.magicloud.lan"}
+            },
+            "spec": {
+                "gatewayClassName": "eg",
+                "listeners": [{"name": "http", "port": 80, "protocol": "HTTP"}]
+            }
+        });
+        let cluster = Cluster::from_objects(ClusterObjects {
+            gateway_classes: vec![
+                serde_json::from_value(json!({
+                    "apiVersion": "gateway.networking.k8s.io/v1",
+                    "kind": "GatewayClass",
+                    "metadata": {"name": "eg"},
+                    "spec": {"controllerName": "gateway.envoyproxy.io/gatewayclass-controller"}
+                }))
+                .unwrap(),
+            ],
+            ..Default::default()
+        });
+        let file = policy_file(
+            r"
+gatewayControllers:
+  gateway.envoyproxy.io/gatewayclass-controller:
+    httpsPort: 10443
+    allowedRoutes:
+      namespaces:
+        from: All
+    listener:
+      tls:
+        options:
+          gateway.envoyproxy.io/tls-min-version: '1.3'
+",
+        );
+        let ret = call_in(
+            cluster,
+            "/mutate",
+            &review(&object),
+            &["--policy-file", file.path().to_str().unwrap()],
+        )
+        .await;
+        let added = &apply(&object, &ret["response"])["spec"]["listeners"][1];
+        assert_eq!(added["port"], 10443);
+        assert_eq!(added["allowedRoutes"]["namespaces"]["from"], "All");
+        assert_eq!(added["tls"]["mode"], "Terminate");
+        assert_eq!(
+            added["tls"]["options"]["gateway.envoyproxy.io/tls-min-version"],
+            "1.3"
+        );
+        assert_eq!(
+            added["tls"]["certificateRefs"][0]["name"],
+            "whoami-https-tls"
+        );
+    }
+
+    #[actix_web::test]
+    async fn gateway_with_cached_plain_httproute_is_denied() {
+        let cluster = Cluster::from_objects(ClusterObjects {
+            httproutes: vec![serde_json::from_value(plain_httproute()).unwrap()],
+            ..Default::default()
+        });
+        let ret = call_in(cluster, "/validate", &review(&gateway()), &[]).await;
+        assert_eq!(ret["response"]["allowed"], false);
+
+        let ret = call("/validate", &review(&gateway()), &[]).await;
+        assert_eq!(ret["response"]["allowed"], true);
+    }
+}
@@ -1,38 +1,64 @@
-use std::{borrow::Cow, fmt::Display, str::FromStr, sync::Arc};
+use std::{
+    borrow::Cow, collections::BTreeMap, fmt::Display, str::FromStr, sync::Arc, time::Duration,
+};
 
+use chrono::{DateTime, TimeDelta, Utc};
 use eyre::{Report, Result, eyre};
 use futures::future::BoxFuture;
 use gateway_api::{
     gateways::{
-        Gateway, GatewayListeners, GatewayListenersAllowedRoutesNamespacesSelectorMatchExpressions,
+        Gateway, GatewayListeners, GatewayListenersAllowedRoutesNamespacesFrom,
+        GatewayListenersAllowedRoutesNamespacesSelectorMatchExpressions,
     },
     httproutes::{
-        HTTPRoute, HTTPRouteParentRefs, HTTPRouteRules, HTTPRouteRulesFilters,
-        HTTPRouteRulesFiltersRequestRedirect, HTTPRouteRulesFiltersRequestRedirectScheme,
+        HTTPRoute, HTTPRouteParentRefs, HTTPRouteRules, HTTPRouteRulesFiltersRequestRedirectScheme,
         HTTPRouteRulesFiltersType, HTTPRouteRulesMatches, HTTPRouteRulesMatchesPath,
         HTTPRouteRulesMatchesPathType,
     },
 };
 use itertools::Itertools;
 use json_patch::Patch;
-use k8s_openapi::api::{core::v1::Namespace, networking::v1::Ingress};
+use k8s_openapi::api::{
+    authentication::v1::UserInfo, authorization::v1::ResourceAttributes, networking::v1::Ingress,
+};
 use kube::{
-    Api, Client,
-    api::{DynamicObject, ListParams, ObjectMeta},
-    core::admission::AdmissionResponse,
+    api::{DynamicObject, ObjectMeta},
+    core::{
+        GroupVersionKind,
+        admission::{AdmissionRequest, AdmissionResponse},
+    },
 };
 use serde::Serialize;
+use strum::{EnumDiscriminants, EnumIter, IntoEnumIterator, IntoStaticStr};
 use tracing::instrument;
 
-use crate::httproute::GatewayListenerPair;
+use crate::{
+    cli::{CertManagerAnnotations, Cli, EnforcementMode, parse_issuer},
+    cluster::Cluster,
+    httproute::GatewayListenerPair,
+};
 
 pub const SKIP_ANNOTATION: &str = "ingress-tls.magiclouds.cn/skip";
+pub const SKIP_UNTIL_ANNOTATION: &str = "ingress-tls.magiclouds.cn/skip-until";
+pub const SKIP_REASON_ANNOTATION: &str = "ingress-tls.magiclouds.cn/skip-reason";
+pub const ENFORCE_LABEL: &str = "ingress-tls.magiclouds.cn/enforce";
+// Namespace annotations overriding `--issuer`, `--kind` and `--group`.
+pub const NAMESPACE_ISSUER_ANNOTATION: &str = "ingress-tls.magiclouds.cn/issuer";
+pub const NAMESPACE_ISSUER_KIND_ANNOTATION: &str = "ingress-tls.magiclouds.cn/issuer-kind";
+pub const NAMESPACE_ISSUER_GROUP_ANNOTATION: &str = "ingress-tls.magiclouds.cn/issuer-group";
+// Virtual API group and verb, for RBAC to grant the skip annotation.
+pub const SKIP_API_GROUP: &str = "ingresstls.magiclouds.cn";
+pub const SKIP_VERB: &str = "skip";
 pub const TRAEFIK_MIDDLEWARE_ANNOTATION: &str = "traefik.ingress.kubernetes.io/router.middlewares";
 pub const NGINX_FORCE_SSL_REDIRECT: &str = "nginx.ingress.kubernetes.io/force-ssl-redirect";
 pub const ISSUER: &str = "cert-manager.io/issuer";
 pub const CLUSTER_ISSUER: &str = "cert-manager.io/cluster-issuer";
 pub const ISSUER_KIND: &str = "cert-manager.io/issuer-kind";
 pub const ISSUER_GROUP: &str = "cert-manager.io/issuer-group";
+pub const ACME_HTTP01_SOLVER_LABEL: &str = "acme.cert-manager.io/http01-solver";
+pub const ACME_CHALLENGE_PATH_PREFIX: &str = "/.well-known/acme-challenge/";
+pub const INGRESS_CLASS_ANNOTATION: &str = "kubernetes.io/ingress.class";
+pub const DEFAULT_INGRESS_CLASS_ANNOTATION: &str = "ingressclass.kubernetes.io/is-default-class";
 
 #[allow(unused_macros)]
 macro_rules! debug_cond {
@@ -121,75 +147,169 @@ impl FromStr for SupportedIngressClass {
         }
     }
 }
-
-#[derive(Debug, Clone)]
-pub enum Issuer {
-    Namespaced(String),
-    Clustered(String),
+impl SupportedIngressClass {
+    pub fn from_controller(controller: &str) -> Result<Self> {
+        match controller {
+            "traefik.io/ingress-controller" => Ok(Self::Traefik),
+            "k8s.io/ingress-nginx" => Ok(Self::Nginx),
+            _ => Err(eyre!("Unsupported Ingress controller {controller}")),
+        }
+    }
 }
 
-pub async fn get_gateway(namespace: &str, name: &str) -> Result<Option<Gateway>> {
-    let client = Client::try_default().await?;
-    let gateways: Api<Gateway> = Api::namespaced(client, namespace);
-    let gateway = gateways.get_opt(name).await?;
-    Ok(gateway)
+// Class name comes from `spec.ingressClassName`, the legacy annotation, or the
+// default IngressClass, in that order. The controller of the IngressClass
+// decides. Class names are only taken literally when there is no such
+// IngressClass, which is the case of the legacy annotation.
+#[instrument(skip_all)]
+pub fn resolve_ingress_class(
+    ingress: &Ingress,
+    cluster: &Cluster,
+) -> Result<SupportedIngressClass> {
+    let name = ingress
+        .spec
+        .as_ref()
+        .and_then(|s| s.ingress_class_name.as_ref())
+        .or_else(|| {
+            ingress
+                .metadata
+                .annotations
+                .as_ref()
+                .and_then(|a| a.get(INGRESS_CLASS_ANNOTATION))
+        });
+    let controller = cluster
+        .get_ingress_class(name.map(String::as_str))
+        .and_then(|ic| ic.spec.as_ref()?.controller.clone());
+    match (controller, name) {
+        (Some(controller), _) => SupportedIngressClass::from_controller(&controller),
+        (None, Some(name)) => SupportedIngressClass::from_str(name),
+        (None, None) => Err(eyre!("Cannot resolve the Ingress Class")),
+    }
 }
 
-pub async fn get_httproutes(namesapces: &Namespaces<'_>) -> Result<Vec<HTTPRoute>> {
-    let client = Client::try_default().await?;
-    let httproute: Vec<Api<HTTPRoute>> = match namesapces {
-        Namespaces::All => {
-            let ns: Api<Namespace> = Api::all(client.clone());
-            let namespaces = ns.list(&ListParams::default()).await?;
-            namespaces
-                .items
-                .into_iter()
-                .filter_map(|x| {
-                    x.metadata
-                        .name
-                        .map(|ns| Api::namespaced(client.clone(), &ns))
+// The namespace label wins over the global default, like Pod Security Admission.
+pub fn resolve_enforcement_mode(
+    default: EnforcementMode,
+    namespace: &str,
+    cluster: &Cluster,
+) -> EnforcementMode {
+    cluster
+        .get_namespace(namespace)
+        .and_then(|ns| ns.metadata.labels.as_ref()?.get(ENFORCE_LABEL).cloned())
+        .and_then(|value| {
+            <EnforcementMode as clap::ValueEnum>::from_str(&value, true)
+                .inspect_err(|e| {
+                    tracing::warn!(
+                        target: "enforcement-mode",
+                        message = format!("Invalid {ENFORCE_LABEL} label on namespace {namespace}: {e}")
+                    );
                 })
-                .collect()
-        }
-        Namespaces::Some(items) => items
-            .iter()
-            .map(|ns| Api::namespaced(client.clone(), ns))
-            .collect(),
+                .ok()
+        })
+        .unwrap_or(default)
+}
+
+// The issuer a namespace asks for, in the format of `--issuer`.
+pub fn get_namespace_issuer(
+    namespace: &str,
+    cluster: &Cluster,
+) -> Result<Option<CertManagerAnnotations>, String> {
+    let Some(annotations) = cluster
+        .get_namespace(namespace)
+        .and_then(|ns| ns.metadata.annotations.clone())
+    else {
+        return Ok(None);
     };
-    let lp = ListParams::default();
-    let mut ret = Vec::new();
-    for api in httproute {
-        let httproutes = api.list(&lp).await?;
-        for i in httproutes.items {
-            if let Some(name) = i.metadata.name {
-                let httproute = api.get(&name).await?;
-                ret.push(httproute);
-            }
+    let kind = annotations.get(NAMESPACE_ISSUER_KIND_ANNOTATION).cloned();
+    let group = annotations.get(NAMESPACE_ISSUER_GROUP_ANNOTATION).cloned();
+    match annotations.get(NAMESPACE_ISSUER_ANNOTATION) {
+        Some(issuer) => Ok(Some(CertManagerAnnotations {
+            issuer: parse_issuer(issuer).map_err(|e| {
+                format!("{NAMESPACE_ISSUER_ANNOTATION} of namespace {namespace}: {e}")
+            })?,
+            kind,
+            group,
+        })),
+        None if kind.is_some() || group.is_some() => Err(format!(
+            "Namespace {namespace} sets the issuer kind or group without {NAMESPACE_ISSUER_ANNOTATION}"
+        )),
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SupportedKind {
+    Ingress,
+    Gateway,
+    #[value(name = "httproute")]
+    HTTPRoute,
+}
+// Keyed on group, version and kind. Served versions of the same kind share the
+// schema as far as this tool concerns, so they are parsed into the same type.
+const SUPPORTED_KINDS: &[(&str, &str, &str, SupportedKind)] = &[
+    ("networking.k8s.io", "v1", "Ingress", SupportedKind::Ingress),
+    (
+        "gateway.networking.k8s.io",
+        "v1",
+        "Gateway",
+        SupportedKind::Gateway,
+    ),
+    (
+        "gateway.networking.k8s.io",
+        "v1beta1",
+        "Gateway",
+        SupportedKind::Gateway,
+    ),
+    (
+        "gateway.networking.k8s.io",
+        "v1",
+        "HTTPRoute",
+        SupportedKind::HTTPRoute,
+    ),
+    (
+        "gateway.networking.k8s.io",
+        "v1beta1",
+        "HTTPRoute",
+        SupportedKind::HTTPRoute,
+    ),
+];
+impl SupportedKind {
+    pub fn from_gvk(gvk: &GroupVersionKind) -> Option<Self> {
+        SUPPORTED_KINDS
+            .iter()
+            .find(|(g, v, k, _)| *g == gvk.group && *v == gvk.version && *k == gvk.kind)
+            .map(|(_, _, _, x)| *x)
+    }
+
+    pub const fn resource(self) -> &'static str {
+        match self {
+            Self::Ingress => "ingresses",
+            Self::Gateway => "gateways",
+            Self::HTTPRoute => "httproutes",
         }
     }
-    Ok(ret)
 }
 
-pub async fn filter_namespaces(selectors: &[SelectorByLabel<'_, '_>]) -> Result<Vec<String>> {
-    let client = Client::try_default().await?;
-    let namespaces: Api<Namespace> = Api::all(client);
-    let lp = ListParams {
-        label_selector: Some(
-            selectors
-                .iter()
-                .map(std::string::ToString::to_string)
-                .collect::<Vec<_>>()
-                .join(","),
-        ),
-        ..Default::default()
-    };
-    Ok(namespaces
-        .list(&lp)
-        .await?
-        .items
-        .into_iter()
-        .filter_map(|x| x.metadata.name)
-        .collect())
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issuer {
+    Namespaced(String),
+    Clustered(String),
+}
+impl Issuer {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Namespaced(x) | Self::Clustered(x) => x,
+        }
+    }
+}
+// As it is given on the command line.
+impl Display for Issuer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Namespaced(x) => write!(f, "namespaced:{x}"),
+            Self::Clustered(x) => write!(f, "clustered:{x}"),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -197,6 +317,14 @@ pub enum Namespaces<'a> {
     All,
     Some(Vec<Cow<'a, str>>),
 }
+impl Namespaces<'_> {
+    pub fn contains(&self, namespace: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Some(items) => items.iter().any(|x| x == namespace),
+        }
+    }
+}
 
 #[allow(dead_code)]
 pub enum SelectorByLabel<'a, 'b> {
@@ -219,6 +347,22 @@ impl Display for SelectorByLabel<'_, '_> {
         }
     }
 }
+impl SelectorByLabel<'_, '_> {
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        match self {
+            Self::Is(k, v) => labels.get(k.as_ref()).is_some_and(|x| x == v),
+            Self::IsNot(k, v) => labels.get(k.as_ref()).is_none_or(|x| x != v),
+            Self::In(k, v) => labels
+                .get(k.as_ref())
+                .is_some_and(|x| v.iter().any(|y| y == x)),
+            Self::NotIn(k, v) => labels
+                .get(k.as_ref())
+                .is_none_or(|x| !v.iter().any(|y| y == x)),
+            Self::Exists(k) => labels.contains_key(k.as_ref()),
+            Self::DoesNotExist(k) => !labels.contains_key(k.as_ref()),
+        }
+    }
+}
 impl From<(String, String)> for SelectorByLabel<'_, '_> {
     fn from(value: (String, String)) -> Self {
         Self::Is(value.0.into(), value.1.into())
@@ -321,21 +465,102 @@ impl From<StatusAdmissionResponse> for AdmissionResponse {
             }
             Status::Denied(msg) => a.deny(format!("{}/{}: {msg}", m.0, m.1)),
             Status::Invalid(msg) => a.deny(format!("{}/{}: {msg}", m.0, m.1)),
-            Status::Patch(_) => unimplemented!(),
+            Status::Patch(p) => {
+                a.allowed = true;
+                if p.0.is_empty() {
+                    a
+                } else {
+                    let fallback = a.clone();
+                    a.with_patch(p).unwrap_or_else(|e| {
+                        tracing::warn!(target: "internal-error", message = format!("{e:?}"));
+                        fallback.deny(format!("{}/{}: Cannot serialize the patch. {e}", m.0, m.1))
+                    })
+                }
+            }
         }
     }
 }
 
-#[derive(Debug)]
+// `Rule` is the reason without its details, one per rule name.
+#[derive(Debug, EnumDiscriminants)]
+#[strum_discriminants(name(Rule), derive(EnumIter, IntoStaticStr))]
 pub enum DenyReason {
     InternalError(Report),
     IngressNoTLS,
+    IngressHostsNotCoveredByTLS(Vec<String>),
     GatewayNoTLSListener,
     GatewayNonRedirectHTTPRouteAttachedToHTTPListener(
         Vec<(GatewayListeners, Parted<Vec<HTTPRoute>>)>,
     ),
     HTTPRouteNonRedirectAttachedToHTTPListener(Vec<(HTTPRouteParentRefs, GatewayListenerPair)>),
     CannotInferenceMutation,
+    SkipNotAuthorized(String),
+    InvalidSkip(String),
+    // Host, issuer.
+    MixedIssuers(Vec<(String, String)>),
+    InvalidIssuer(String),
+}
+impl DenyReason {
+    pub fn name(&self) -> &'static str {
+        Rule::from(self).into()
+    }
+
+    pub fn description(&self) -> &'static str {
+        Rule::from(self).description()
+    }
+
+    pub fn is_toggleable(&self) -> bool {
+        Rule::from(self).is_toggleable()
+    }
+}
+impl Rule {
+    // Of the rule, whatever the details, such as in SARIF reports.
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::InternalError => "The object cannot be processed",
+            Self::IngressNoTLS => "Ingress has no TLS configuration",
+            Self::IngressHostsNotCoveredByTLS => {
+                "Ingress hosts are not covered by its TLS configuration"
+            }
+            Self::GatewayNoTLSListener => "Gateway has no HTTPS listener",
+            Self::GatewayNonRedirectHTTPRouteAttachedToHTTPListener => {
+                "Non-redirect HTTPRoutes are attached to HTTP listeners of the Gateway"
+            }
+            Self::HTTPRouteNonRedirectAttachedToHTTPListener => {
+                "Non-redirect HTTPRoute is attached to HTTP listeners"
+            }
+            Self::CannotInferenceMutation => "Not enough information to make the mutation",
+            Self::SkipNotAuthorized => "Skip annotation used without permission",
+            Self::InvalidSkip => "Skip annotation is not valid",
+            Self::MixedIssuers => "Hosts of the object map to different issuers",
+            Self::InvalidIssuer => "The issuer to annotate with is not valid or does not exist",
+        }
+    }
+
+    // Whether the `checks` setting of the policy file can turn the rule off.
+    pub const fn is_toggleable(self) -> bool {
+        matches!(
+            self,
+            Self::IngressNoTLS
+                | Self::IngressHostsNotCoveredByTLS
+                | Self::GatewayNoTLSListener
+                | Self::GatewayNonRedirectHTTPRouteAttachedToHTTPListener
+                | Self::HTTPRouteNonRedirectAttachedToHTTPListener
+        )
+    }
+
+    pub fn description_of(name: &str) -> Option<&'static str> {
+        Self::iter()
+            .find(|x| <&str>::from(x) == name)
+            .map(Self::description)
+    }
+
+    pub fn toggleable_names() -> Vec<&'static str> {
+        Self::iter()
+            .filter(|x| x.is_toggleable())
+            .map(Into::into)
+            .collect()
+    }
 }
 impl Display for DenyReason {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -346,6 +571,10 @@ impl Display for DenyReason {
                 f.write_str(&format!("Internal Error occurred.\n{report:?}"))
             }
             Self::IngressNoTLS => f.write_str("The Ingress does not contain a TLS configuration."),
+            Self::IngressHostsNotCoveredByTLS(hosts) => f.write_str(&format!(
+                "The TLS configuration of the Ingress does not cover hosts: {}",
+                hosts.join(", ")
+            )),
             Self::GatewayNoTLSListener => {
                 f.write_str("The Gateway does not contain a TLS configuration.")
             }
@@ -378,6 +607,20 @@ impl Display for DenyReason {
             Self::CannotInferenceMutation => {
                 f.write_str("There is not enough information to make the mutation")
             }
+            Self::SkipNotAuthorized(user) => f.write_str(&format!(
+                "{user} is not allowed to skip the checks with the {SKIP_ANNOTATION} annotation."
+            )),
+            Self::InvalidSkip(e) => f.write_str(&format!(
+                "The {SKIP_ANNOTATION} annotation is not valid. {e}."
+            )),
+            Self::MixedIssuers(hosts) => f.write_str(&format!(
+                "The hosts map to different issuers, and splitting TLS entries or listeners per issuer is not supported, as cert-manager takes one issuer per object. Move them into objects of their own: {}",
+                hosts
+                    .iter()
+                    .map(|(host, issuer)| format!("{host} ({issuer})"))
+                    .join(", ")
+            )),
+            Self::InvalidIssuer(e) => f.write_str(&format!("The issuer is not valid. {e}.")),
         }
     }
 }
@@ -400,20 +643,16 @@ impl ControlFlow for Option<Result<Status>> {
     }
 }
 
-pub type AsyncClosure<'a, I, O> = Box<dyn Fn(Arc<I>) -> BoxFuture<'a, O>>;
+pub type AsyncClosure<'a, I, O> = Box<dyn Fn(Arc<I>) -> BoxFuture<'a, O> + Send + Sync + 'a>;
 pub struct Checks<'a, I, O>(Vec<AsyncClosure<'a, I, O>>);
 impl<I, O: ControlFlow> Checks<'_, I, O> {
     pub async fn run(&self, input: Arc<I>) -> O {
         let mut accum = O::initialize_value();
         for check in &self.0 {
-            let x = input.clone();
-            if accum.is_continue() {
-                accum = check(x).await;
-            } else if accum.is_break() {
+            if accum.is_break() {
                 break;
-            } else {
-                unimplemented!()
             }
+            accum = check(input.clone()).await;
         }
         accum
     }
@@ -423,6 +662,34 @@ impl<'a, I, O> From<Vec<AsyncClosure<'a, I, O>>> for Checks<'a, I, O> {
         Self(value)
     }
 }
+impl<'a, I: Send + Sync + 'a> Checks<'a, I, Option<Result<Status>>> {
+    // Denials of the checks turned off by the policy let the object go on.
+    pub fn toggled(self, conf: &Cli) -> Self {
+        let policy = Arc::new(conf.policy.clone());
+        Self(
+            self.0
+                .into_iter()
+                .map(|check| -> AsyncClosure<'a, I, Option<Result<Status>>> {
+                    let policy = policy.clone();
+                    Box::new(move |o| {
+                        let policy = policy.clone();
+                        let ret = check(o);
+                        Box::pin(async move {
+                            match ret.await {
+                                Some(Ok(Status::Denied(reason)))
+                                    if !policy.is_check_enabled(reason.name()) =>
+                                {
+                                    Some(Ok(Status::MoveOn))
+                                }
+                                x => x,
+                            }
+                        })
+                    })
+                })
+                .collect(),
+        )
+    }
+}
 
 pub trait HasMetadata {
     fn get_metadata(&self) -> &ObjectMeta;
@@ -442,6 +709,207 @@ impl HasMetadata for HTTPRoute {
         &self.metadata
     }
 }
+impl HasMetadata for DynamicObject {
+    fn get_metadata(&self) -> &ObjectMeta {
+        &self.metadata
+    }
+}
+
+// Who is asking, from the admission request.
+#[derive(Debug, Clone, Default)]
+pub struct Requester {
+    pub user_info: UserInfo,
+    pub resource: String,
+    pub namespace: Option<String>,
+    // Checking manifests, not knowing who will apply them.
+    pub offline: bool,
+    // Of an UPDATE.
+    pub old_object: Option<DynamicObject>,
+}
+impl From<&AdmissionRequest<DynamicObject>> for Requester {
+    fn from(req: &AdmissionRequest<DynamicObject>) -> Self {
+        Self {
+            user_info: req.user_info.clone(),
+            resource: req.resource.resource.clone(),
+            namespace: req.namespace.clone(),
+            offline: false,
+            old_object: req.old_object.clone(),
+        }
+    }
+}
+impl Requester {
+    // Whether the skip annotations are added or changed, compared with the
+    // old object. Editing an object already exempted keeps the exemption.
+    fn changes_skip(&self, metadata: &ObjectMeta) -> bool {
+        let Some(old) = &self.old_object else {
+            return true;
+        };
+        let skip = |m: &ObjectMeta| {
+            [
+                SKIP_ANNOTATION,
+                SKIP_UNTIL_ANNOTATION,
+                SKIP_REASON_ANNOTATION,
+            ]
+            .map(|k| m.annotations.as_ref().and_then(|a| a.get(k)).cloned())
+        };
+        skip(&old.metadata) != skip(metadata)
+    }
+}
+
+struct SkipAuthorizer {
+    max_duration: Option<Duration>,
+    users: Vec<String>,
+    groups: Vec<String>,
+    subject_access_review: bool,
+    cluster: Cluster,
+    requester: Requester,
+}
+impl SkipAuthorizer {
+    async fn is_allowed(&self) -> Result<bool> {
+        // Admission decides on applying.
+        if self.requester.offline {
+            return Ok(true);
+        }
+        let user_info = &self.requester.user_info;
+        if user_info
+            .username
+            .as_ref()
+            .is_some_and(|u| self.users.contains(u))
+            || user_info
+                .groups
+                .iter()
+                .flatten()
+                .any(|g| self.groups.contains(g))
+        {
+            return Ok(true);
+        }
+        if self.subject_access_review {
+            return self
+                .cluster
+                .review_access(
+                    user_info,
+                    ResourceAttributes {
+                        group: Some(SKIP_API_GROUP.to_string()),
+                        resource: Some(self.requester.resource.clone()),
+                        namespace: self.requester.namespace.clone(),
+                        verb: Some(SKIP_VERB.to_string()),
+                        ..Default::default()
+                    },
+                )
+                .await;
+        }
+        Ok(false)
+    }
+}
+
+// The skip annotation is only honored for the requesters allowed to use it.
+pub fn skip_check<'a, K: HasMetadata + Send + Sync + 'a>(
+    conf: &Cli,
+    cluster: &Cluster,
+    requester: &Requester,
+) -> AsyncClosure<'a, K, Option<Result<Status>>> {
+    let policy = Arc::new(conf.policy.clone());
+    let authorizer = Arc::new(SkipAuthorizer {
+        max_duration: conf.skip_max_duration,
+        users: conf.skip_allowed_users.clone(),
+        groups: conf.skip_allowed_groups.clone(),
+        subject_access_review: conf.skip_subject_access_review,
+        cluster: cluster.clone(),
+        requester: requester.clone(),
+    });
+    Box::new(move |o| {
+        let authorizer = authorizer.clone();
+        let policy = policy.clone();
+        Box::pin(async move {
+            let ns = o
+                .get_metadata()
+                .namespace
+                .as_ref()
+                .or(authorizer.requester.namespace.as_ref());
+            if ns.is_some_and(|ns| policy.is_exempt(ns)) {
+                tracing::info!(target: "audit", message = format!("Namespace {ns:?} exempted by the policy"));
+                return Some(Ok(Status::Allowed));
+            }
+            match get_skip_state(o.as_ref(), authorizer.max_duration, Utc::now()) {
+                // An expired exemption is flagged by the webhook, the checks apply again.
+                SkipState::NotRequested | SkipState::Expired(_) => {
+                    return Some(Ok(Status::MoveOn));
+                }
+                SkipState::Invalid(e) => {
+                    return Some(Ok(Status::Denied(DenyReason::InvalidSkip(e))));
+                }
+                SkipState::Active => {}
+            }
+            // Only an exemption still active, by the expiry and the maximum
+            // duration checked above on every request, is kept.
+            if !authorizer.requester.changes_skip(o.get_metadata()) {
+                tracing::info!(target: "audit", message = "Skip annotation kept from the old object");
+                return Some(Ok(Status::Allowed));
+            }
+            let username = authorizer
+                .requester
+                .user_info
+                .username
+                .clone()
+                .unwrap_or_default();
+            let ret = authorizer.is_allowed().await.map(|allowed| {
+                if allowed {
+                    tracing::info!(target: "audit", message = format!("Skip annotation honored for {username}"));
+                    Status::Allowed
+                } else {
+                    Status::Denied(DenyReason::SkipNotAuthorized(username))
+                }
+            });
+            Some(ret)
+        })
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipState {
+    NotRequested,
+    Active,
+    Expired(DateTime<Utc>),
+    Invalid(String),
+}
+
+// Exemptions need a reason, and a deadline when there is a maximum duration.
+pub fn get_skip_state(
+    o: &impl HasMetadata,
+    max_duration: Option<Duration>,
+    now: DateTime<Utc>,
+) -> SkipState {
+    if get_skip(o).is_none_or(|x| x != "true") {
+        return SkipState::NotRequested;
+    }
+    let annotations = o.get_metadata().annotations.as_ref();
+    let annotation = |key| annotations.and_then(|a| a.get(key));
+    if annotation(SKIP_REASON_ANNOTATION).is_none_or(|x| x.trim().is_empty()) {
+        return SkipState::Invalid(format!("{SKIP_REASON_ANNOTATION} is required"));
+    }
+    let until = match annotation(SKIP_UNTIL_ANNOTATION).map(|x| DateTime::parse_from_rfc3339(x)) {
+        Some(Ok(until)) => Some(until.to_utc()),
+        Some(Err(e)) => {
+            return SkipState::Invalid(format!(
+                "{SKIP_UNTIL_ANNOTATION} is not an RFC3339 date, {e}"
+            ));
+        }
+        None => None,
+    };
+    match (until, max_duration) {
+        (Some(until), _) if until <= now => SkipState::Expired(until),
+        (Some(until), Some(max))
+            if until - now > TimeDelta::from_std(max).unwrap_or(TimeDelta::MAX) =>
+        {
+            SkipState::Invalid(format!(
+                "{SKIP_UNTIL_ANNOTATION} is more than {} away",
+                humantime::format_duration(max)
+            ))
+        }
+        (None, Some(_)) => SkipState::Invalid(format!("{SKIP_UNTIL_ANNOTATION} is required")),
+        _ => SkipState::Active,
+    }
+}
 
 pub fn get_skip(o: &impl HasMetadata) -> Option<&String> {
     let skip = o
@@ -470,47 +938,149 @@ pub fn get_external_dns_hostname(o: &impl HasMetadata) -> Option<Vec<String>> {
     })
 }
 
+// cert-manager labels the solver resources, and they are owned by the
+// Challenge. The path shape is checked by the callers, so that the label alone
+// cannot exempt an application.
+pub fn is_acme_http01_solver(o: &impl HasMetadata) -> bool {
+    let metadata = o.get_metadata();
+    metadata
+        .labels
+        .as_ref()
+        .and_then(|l| l.get(ACME_HTTP01_SOLVER_LABEL))
+        .is_some_and(|x| x == "true")
+        || metadata.owner_references.as_ref().is_some_and(|o_s| {
+            o_s.iter().any(|o| {
+                o.kind == "Challenge" && o.api_version.starts_with("acme.cert-manager.io/")
+            })
+        })
+}
+
+pub fn is_acme_http01_solver_httproute(httproute: &HTTPRoute) -> bool {
+    is_acme_http01_solver(httproute)
+        && httproute.spec.rules.as_ref().is_some_and(|rules| {
+            !rules.is_empty()
+                && rules.iter().all(|rule| {
+                    rule.matches.as_ref().is_some_and(|matches| {
+                        !matches.is_empty()
+                            && matches.iter().all(|m| {
+                                m.path
+                                    .as_ref()
+                                    .and_then(|p| p.value.as_ref())
+                                    .is_some_and(|v| v.starts_with(ACME_CHALLENGE_PATH_PREFIX))
+                            })
+                    })
+                })
+        })
+}
+
+pub fn audit_acme_http01_solver(o: &impl HasMetadata) {
+    let metadata = o.get_metadata();
+    tracing::info!(
+        target: "audit",
+        message = format!(
+            "Allowing cert-manager ACME HTTP-01 solver {}/{}",
+            metadata.namespace.as_deref().unwrap_or_default(),
+            metadata.name.as_deref().unwrap_or_default()
+        )
+    );
+}
+
+// DNS names are case insensitive, and the trailing dot of FQDN is common in
+// external-dns annotations.
+pub fn normalize_hostname(hostname: &str) -> String {
+    hostname.trim().trim_end_matches('.').to_lowercase()
+}
+
+// A wildcard covers exactly one non-wildcard label. Otherwise names have to be
+// the same.
+pub fn does_hostname_cover(pattern: &str, hostname: &str) -> bool {
+    let pattern = normalize_hostname(pattern);
+    let hostname = normalize_hostname(hostname);
+    pattern == hostname
+        || pattern.strip_prefix("*.").is_some_and(|suffix| {
+            hostname
+                .split_once('.')
+                .is_some_and(|(label, rest)| !label.is_empty() && label != "*" && rest == suffix)
+        })
+}
+
+// Whether every request reaching the route is redirected to https, or not
+// served at all. Each rule must either redirect to https, not serve anything
+// (no filters and no backends), or be shadowed by an identical match of an
+// earlier redirecting rule, which wins the tie by list order.
 #[instrument(skip_all)]
 pub fn is_redirect_or_no_rule(httproute: &HTTPRoute) -> bool {
-    let try_closure = || {
-        httproute.spec.rules.as_ref().map_or(Some(true), |rules| {
-            if rules.is_empty() {
-                // another kind of no rules.
-                Some(true)
-            } else if rules.len() == 1
-                && rules.first().is_some_and(|x| {
-                    let y = HTTPRouteRules {
-                        matches: Some(vec![HTTPRouteRulesMatches {
-                            path: Some(HTTPRouteRulesMatchesPath {
-                                r#type: Some(HTTPRouteRulesMatchesPathType::PathPrefix),
-                                value: Some("/".to_string()),
-                            }),
-                            ..Default::default()
-                        }]),
-                        filters: Some(vec![HTTPRouteRulesFilters {
-                            r#type: HTTPRouteRulesFiltersType::RequestRedirect,
-                            request_redirect: Some(HTTPRouteRulesFiltersRequestRedirect {
-                                scheme: Some(HTTPRouteRulesFiltersRequestRedirectScheme::Https),
-                                status_code: Some(302),
-                                ..Default::default()
-                            }),
-                            ..Default::default()
-                        }]),
-                        ..Default::default()
-                    };
-                    x == &y
+    let Some(rules) = httproute.spec.rules.as_ref() else {
+        return true;
+    };
+    let mut redirected_matches = vec![];
+    rules.iter().all(|rule| {
+        let matches = defaulted_matches(rule);
+        if is_https_redirect_rule(rule) {
+            redirected_matches.extend(matches);
+            true
+        } else {
+            is_serving_nothing_rule(rule) || matches.iter().all(|m| redirected_matches.contains(m))
+        }
+    })
+}
+
+fn is_https_redirect_rule(rule: &HTTPRouteRules) -> bool {
+    rule.filters.as_ref().is_some_and(|filters| {
+        filters.iter().any(|f| {
+            f.r#type == HTTPRouteRulesFiltersType::RequestRedirect
+                && f.request_redirect.as_ref().is_some_and(|r| {
+                    // Omitted scheme means the scheme of the request, which is http.
+                    r.scheme == Some(HTTPRouteRulesFiltersRequestRedirectScheme::Https)
                 })
-            {
-                // one and only redirect rule
-                Some(true)
-            } else {
-                None
-            }
         })
+    })
+}
+
+// Requests are answered with errors by the implementation.
+fn is_serving_nothing_rule(rule: &HTTPRouteRules) -> bool {
+    rule.filters.as_ref().is_none_or(Vec::is_empty)
+        && rule.backend_refs.as_ref().is_none_or(Vec::is_empty)
+}
+
+// Omitted matches and paths default to `PathPrefix /`.
+fn defaulted_matches(rule: &HTTPRouteRules) -> Vec<HTTPRouteRulesMatches> {
+    let default_path = || HTTPRouteRulesMatchesPath {
+        r#type: Some(HTTPRouteRulesMatchesPathType::PathPrefix),
+        value: Some("/".to_string()),
     };
-    try_closure().unwrap_or_default()
+    let matches = rule
+        .matches
+        .as_ref()
+        .filter(|x| !x.is_empty())
+        .cloned()
+        .unwrap_or_else(|| vec![HTTPRouteRulesMatches::default()]);
+    matches
+        .into_iter()
+        .map(|mut m| {
+            let mut path = m.path.take().unwrap_or_else(default_path);
+            path.r#type = path
+                .r#type
+                .or(Some(HTTPRouteRulesMatchesPathType::PathPrefix));
+            path.value = path.value.or_else(|| Some("/".to_string()));
+            m.path = Some(path);
+            m.headers = m.headers.filter(|x| !x.is_empty());
+            m.query_params = m.query_params.filter(|x| !x.is_empty());
+            m
+        })
+        .collect()
 }
 
+// The group and the kind of a parentRef default to a Gateway.
+pub fn is_gateway_parentref(p: &HTTPRouteParentRefs) -> bool {
+    p.group
+        .as_ref()
+        .is_none_or(|g| g == "gateway.networking.k8s.io")
+        && p.kind.as_ref().is_none_or(|k| k == "Gateway")
+}
+
+// Without sectionName and port, the parentRef attaches to every listener of
+// the Gateway.
 pub fn does_parentref_listener_match(
     p: &HTTPRouteParentRefs,
     l: &GatewayListeners,
@@ -519,13 +1089,81 @@ pub fn does_parentref_listener_match(
     hns: &str,
 ) -> bool {
     let hns = hns.to_string();
-    p.kind == Some("Gateway".to_string())
+    is_gateway_parentref(p)
         && p.name == gn
         && p.namespace.as_ref().unwrap_or(&hns) == gns
         && p.section_name.as_ref().is_none_or(|psn| psn == &l.name)
         && p.port.is_none_or(|pp| pp == l.port)
 }
 
+// The namespaces a listener accepts routes from, by its allowedRoutes, the
+// namespace of the Gateway by default. Label selectors are evaluated against
+// the namespaces the cluster knows.
+pub fn get_allowed_namespaces<'a>(
+    listener: &GatewayListeners,
+    gateway_namespace: &'a str,
+    cluster: &Cluster,
+) -> Result<Namespaces<'a>> {
+    let ns_sel = listener
+        .allowed_routes
+        .as_ref()
+        .and_then(|x| x.namespaces.as_ref());
+    match ns_sel.and_then(|x| x.from.as_ref()) {
+        Some(GatewayListenersAllowedRoutesNamespacesFrom::All) => Ok(Namespaces::All),
+        Some(GatewayListenersAllowedRoutesNamespacesFrom::Selector) => {
+            // `Selector` without a selector selects nothing.
+            let Some(s) = ns_sel.and_then(|x| x.selector.as_ref()) else {
+                return Ok(Namespaces::Some(vec![]));
+            };
+            let mut selectors = s.match_expressions.as_ref().map_or(Ok(vec![]), |x| {
+                x.iter()
+                    .map(|x| x.clone().try_into())
+                    .collect::<Result<Vec<_>>>()
+            })?;
+            selectors.extend(s.match_labels.iter().flatten().map(Into::into));
+            let nss = cluster.filter_namespaces(&selectors);
+            Ok(Namespaces::Some(nss.into_iter().map(Into::into).collect()))
+        }
+        Some(GatewayListenersAllowedRoutesNamespacesFrom::Same) | None => {
+            Ok(Namespaces::Some(vec![gateway_namespace.into()]))
+        }
+    }
+}
+
+// Whether the kinds of the allowedRoutes of a listener include HTTPRoute.
+// Without kinds, HTTP and HTTPS listeners take HTTPRoutes.
+pub fn does_listener_allow_httproute_kind(listener: &GatewayListeners) -> bool {
+    listener
+        .allowed_routes
+        .as_ref()
+        .and_then(|x| x.kinds.as_ref())
+        .filter(|x| !x.is_empty())
+        .map_or(
+            listener.protocol == "HTTP" || listener.protocol == "HTTPS",
+            |kinds| {
+                kinds.iter().any(|k| {
+                    k.kind == "HTTPRoute"
+                        && k.group
+                            .as_ref()
+                            .is_none_or(|g| g == "gateway.networking.k8s.io")
+                })
+            },
+        )
+}
+
+// Whether a listener accepts an HTTPRoute from the namespace, by its
+// allowedRoutes.
+pub fn does_listener_allow_httproute(
+    listener: &GatewayListeners,
+    gateway_namespace: &str,
+    httproute_namespace: &str,
+    cluster: &Cluster,
+) -> Result<bool> {
+    Ok(does_listener_allow_httproute_kind(listener)
+        && get_allowed_namespaces(listener, gateway_namespace, cluster)?
+            .contains(httproute_namespace))
+}
+
 #[derive(Debug)]
 pub struct Parted<T> {
     pub good: T,
@@ -539,3 +1177,445 @@ impl<T> Parted<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use gateway_api::httproutes::HTTPRouteSpec;
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::{
+        metrics,
+        testing::{call, ingress, review},
+    };
+
+    fn route(rules: Option<Value>) -> HTTPRoute {
+        HTTPRoute {
+            metadata: ObjectMeta::default(),
+            spec: HTTPRouteSpec {
+                rules: rules.map(|x| serde_json::from_value(x).unwrap()),
+                ..Default::default()
+            },
+            status: None,
+        }
+    }
+
+    fn redirect(redirect: &Value) -> Value {
+        json!({"filters": [{"type": "RequestRedirect", "requestRedirect": redirect}]})
+    }
+
+    #[test]
+    fn reasons_are_looked_up_by_name() {
+        assert_eq!(
+            Rule::description_of("GatewayNoTLSListener"),
+            Some("Gateway has no HTTPS listener")
+        );
+        assert_eq!(Rule::description_of("NoSuchRule"), None);
+        assert_eq!(
+            Rule::toggleable_names(),
+            [
+                "IngressNoTLS",
+                "IngressHostsNotCoveredByTLS",
+                "GatewayNoTLSListener",
+                "GatewayNonRedirectHTTPRouteAttachedToHTTPListener",
+                "HTTPRouteNonRedirectAttachedToHTTPListener"
+            ]
+        );
+    }
+
+    #[test]
+    fn parentref_matching_follows_gateway_api() {
+        let listener = GatewayListeners {
+            name: "http".to_string(),
+            port: 8000,
+            protocol: "HTTP".to_string(),
+            ..Default::default()
+        };
+        let cases = [
+            ("kind defaults to Gateway", json!({"name": "whoami"}), true),
+            (
+                "group defaults to Gateway API",
+                json!({"group": "gateway.networking.k8s.io", "kind": "Gateway", "name": "whoami"}),
+                true,
+            ),
+            (
+                "other kind",
+                json!({"kind": "Service", "name": "whoami"}),
+                false,
+            ),
+            (
+                "other group",
+                json!({"group": "networking.istio.io", "kind": "Gateway", "name": "whoami"}),
+                false,
+            ),
+            ("other gateway", json!({"name": "other"}), false),
+            (
+                "other namespace",
+                json!({"name": "whoami", "namespace": "other"}),
+                false,
+            ),
+            (
+                "section name",
+                json!({"name": "whoami", "sectionName": "http"}),
+                true,
+            ),
+            (
+                "other section name",
+                json!({"name": "whoami", "sectionName": "https"}),
+                false,
+            ),
+            ("port", json!({"name": "whoami", "port": 8000}), true),
+            ("other port", json!({"name": "whoami", "port": 8443}), false),
+            (
+                "section name and other port",
+                json!({"name": "whoami", "sectionName": "http", "port": 8443}),
+                false,
+            ),
+        ];
+        for (case, parentref, expected) in cases {
+            let parentref: HTTPRouteParentRefs = serde_json::from_value(parentref).unwrap();
+            assert_eq!(
+                does_parentref_listener_match(&parentref, &listener, "whoami", "test", "test"),
+                expected,
+                "{case}"
+            );
+        }
+    }
+
+    #[test]
+    fn https_redirect_decision_table() {
+        let https = json!({"scheme": "https"});
+        let backend = json!({"backendRefs": [{"name": "whoami", "port": 80}]});
+        let cases = [
+            ("no rules", None, true),
+            ("empty rules", Some(json!([])), true),
+            (
+                "redirect without matches",
+                Some(json!([redirect(&https)])),
+                true,
+            ),
+            (
+                "302 on PathPrefix /",
+                Some(json!([{
+                    "matches": [{"path": {"type": "PathPrefix", "value": "/"}}],
+                    "filters": [{"type": "RequestRedirect", "requestRedirect": {"scheme": "https", "statusCode": 302}}]
+                }])),
+                true,
+            ),
+            (
+                "301",
+                Some(json!([redirect(
+                    &json!({"scheme": "https", "statusCode": 301})
+                )])),
+                true,
+            ),
+            (
+                "308",
+                Some(json!([redirect(
+                    &json!({"scheme": "https", "statusCode": 308})
+                )])),
+                true,
+            ),
+            (
+                "explicit port 443",
+                Some(json!([redirect(&json!({"scheme": "https", "port": 443}))])),
+                true,
+            ),
+            (
+                "header matches",
+                Some(json!([{
+                    "matches": [{"headers": [{"name": "x-forwarded-proto", "value": "http"}]}],
+                    "filters": [{"type": "RequestRedirect", "requestRedirect": {"scheme": "https"}}]
+                }])),
+                true,
+            ),
+            (
+                "several redirect rules",
+                Some(json!([
+                    {
+                        "matches": [{"path": {"type": "PathPrefix", "value": "/a"}}],
+                        "filters": [{"type": "RequestRedirect", "requestRedirect": {"scheme": "https"}}]
+                    },
+                    redirect(&json!({"scheme": "https", "statusCode": 301})),
+                ])),
+                true,
+            ),
+            (
+                "backend shadowed by an earlier identical redirect",
+                Some(json!([
+                    {
+                        "matches": [{"path": {"type": "PathPrefix", "value": "/"}}],
+                        "filters": [{"type": "RequestRedirect", "requestRedirect": {"scheme": "https"}}]
+                    },
+                    backend,
+                ])),
+                true,
+            ),
+            (
+                "rule serving nothing",
+                Some(
+                    json!([redirect(&https), {"matches": [{"path": {"type": "Exact", "value": "/x"}}]}]),
+                ),
+                true,
+            ),
+        ];
+        for (name, rules, expected) in cases {
+            assert_eq!(is_redirect_or_no_rule(&route(rules)), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn non_https_redirect_decision_table() {
+        let https = json!({"scheme": "https"});
+        let backend = json!({"backendRefs": [{"name": "whoami", "port": 80}]});
+        let cases = [
+            (
+                "port only keeps http",
+                Some(json!([redirect(&json!({"port": 443}))])),
+                false,
+            ),
+            (
+                "http scheme",
+                Some(json!([redirect(&json!({"scheme": "http"}))])),
+                false,
+            ),
+            ("backend", Some(json!([backend])), false),
+            (
+                "redirect and backend on a longer prefix",
+                Some(json!([
+                    redirect(&https),
+                    {
+                        "matches": [{"path": {"type": "PathPrefix", "value": "/api"}}],
+                        "backendRefs": [{"name": "whoami", "port": 80}]
+                    },
+                ])),
+                false,
+            ),
+            (
+                "backend before an identical redirect",
+                Some(json!([backend, redirect(&https)])),
+                false,
+            ),
+        ];
+        for (name, rules, expected) in cases {
+            assert_eq!(is_redirect_or_no_rule(&route(rules)), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn hostname_coverage() {
+        assert!(does_hostname_cover("a.example.com", "a.example.com"));
+        assert!(does_hostname_cover("A.Example.com", "a.example.com."));
+        assert!(does_hostname_cover("*.example.com", "a.example.com"));
+        assert!(does_hostname_cover("*.example.com", "*.example.com"));
+        assert!(!does_hostname_cover("*.example.com", "example.com"));
+        assert!(!does_hostname_cover("*.example.com", "a.b.example.com"));
+        assert!(!does_hostname_cover("*.example.com", "a.example.org"));
+        assert!(!does_hostname_cover("a.example.com", "*.example.com"));
+        assert!(!does_hostname_cover("*.b.example.com", "*.example.com"));
+    }
+
+    #[test]
+    fn skip_state_decision_table() {
+        let now = DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let month = Some(Duration::from_hours(30 * 24));
+        let state = |annotations: &[(&str, &str)], max_duration| {
+            let route = HTTPRoute {
+                metadata: ObjectMeta {
+                    annotations: Some(
+                        annotations
+                            .iter()
+                            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                            .collect(),
+                    ),
+                    ..Default::default()
+                },
+                spec: HTTPRouteSpec::default(),
+                status: None,
+            };
+            get_skip_state(&route, max_duration, now)
+        };
+        let skip = (SKIP_ANNOTATION, "true");
+        let reason = (SKIP_REASON_ANNOTATION, "Legacy client");
+
+        assert_eq!(state(&[], None), SkipState::NotRequested);
+        assert_eq!(
+            state(&[(SKIP_ANNOTATION, "yes"), reason], None),
+            SkipState::NotRequested
+        );
+        assert_eq!(state(&[skip, reason], None), SkipState::Active);
+        assert!(matches!(state(&[skip], None), SkipState::Invalid(_)));
+        assert!(matches!(
+            state(&[skip, (SKIP_REASON_ANNOTATION, " ")], None),
+            SkipState::Invalid(_)
+        ));
+        assert!(matches!(
+            state(&[skip, reason], month),
+            SkipState::Invalid(_)
+        ));
+        assert!(matches!(
+            state(&[skip, reason, (SKIP_UNTIL_ANNOTATION, "next week")], None),
+            SkipState::Invalid(_)
+        ));
+        assert_eq!(
+            state(
+                &[
+                    skip,
+                    reason,
+                    (SKIP_UNTIL_ANNOTATION, "2026-01-15T00:00:00Z")
+                ],
+                month
+            ),
+            SkipState::Active
+        );
+        assert!(matches!(
+            state(
+                &[
+                    skip,
+                    reason,
+                    (SKIP_UNTIL_ANNOTATION, "2026-03-01T00:00:00Z")
+                ],
+                month
+            ),
+            SkipState::Invalid(_)
+        ));
+        assert_eq!(
+            state(
+                &[
+                    skip,
+                    reason,
+                    (SKIP_UNTIL_ANNOTATION, "2025-12-31T00:00:00+08:00")
+                ],
+                month
+            ),
+            SkipState::Expired(
+                DateTime::parse_from_rfc3339("2025-12-30T16:00:00Z")
+                    .unwrap()
+                    .to_utc()
+            )
+        );
+    }
+
+    #[actix_web::test]
+    async fn skip_requires_permission() {
+        let mut object = ingress("nginx", None);
+        object["metadata"]["annotations"] = json!({
+            SKIP_ANNOTATION: "true",
+            SKIP_REASON_ANNOTATION: "Legacy client"
+        });
+
+        let ret = call("/validate", &review(&object), &[]).await;
+        assert_eq!(ret["response"]["allowed"], false);
+        let message = ret["response"]["status"]["message"].as_str().unwrap();
+        assert!(message.contains("admin is not allowed to skip"));
+
+        let ret = call(
+            "/validate",
+            &review(&object),
+            &["--skip-allowed-users", "someone,admin"],
+        )
+        .await;
+        assert_eq!(ret["response"]["allowed"], true);
+
+        // No group is allowed by default.
+        let mut skip_review = review(&object);
+        skip_review["request"]["userInfo"]["groups"] = json!(["system:masters"]);
+        let ret = call("/validate", &skip_review, &[]).await;
+        assert_eq!(ret["response"]["allowed"], false);
+        let ret = call(
+            "/validate",
+            &skip_review,
+            &["--skip-allowed-groups", "system:masters"],
+        )
+        .await;
+        assert_eq!(ret["response"]["allowed"], true);
+    }
+
+    #[actix_web::test]
+    async fn update_of_skipped_object_keeps_the_skip() {
+        let mut old_object = ingress("nginx", None);
+        old_object["metadata"]["annotations"] = json!({
+            SKIP_ANNOTATION: "true",
+            SKIP_REASON_ANNOTATION: "Legacy client"
+        });
+        let mut object = old_object.clone();
+        object["metadata"]["labels"] = json!({"app": "whoami"});
+        let update = |old_object: &Value, object: &Value| {
+            let mut review = review(object);
+            review["request"]["operation"] = json!("UPDATE");
+            review["request"]["oldObject"] = old_object.clone();
+            review["request"]["options"]["kind"] = json!("UpdateOptions");
+            review
+        };
+
+        // admin is not allowed to skip, but only edits the labels.
+        let ret = call("/validate", &update(&old_object, &object), &[]).await;
+        assert_eq!(ret["response"]["allowed"], true);
+
+        // The kept exemption is still bound by the maximum duration.
+        let ret = call(
+            "/validate",
+            &update(&old_object, &object),
+            &["--skip-max-duration", "30d"],
+        )
+        .await;
+        assert_eq!(ret["response"]["allowed"], false);
+
+        // And expires, whoever edits the object.
+        old_object["metadata"]["annotations"][SKIP_UNTIL_ANNOTATION] =
+            json!("2020-01-01T00:00:00Z");
+        let mut expired = old_object.clone();
+        expired["metadata"]["labels"] = json!({"app": "whoami"});
+        let ret = call("/validate", &update(&old_object, &expired), &[]).await;
+        assert_eq!(ret["response"]["allowed"], false);
+        let message = ret["response"]["status"]["message"].as_str().unwrap();
+        assert!(message.contains("does not contain a TLS configuration"));
+
+        object["metadata"]["annotations"][SKIP_REASON_ANNOTATION] = json!("Another reason");
+        let ret = call("/validate", &update(&old_object, &object), &[]).await;
+        assert_eq!(ret["response"]["allowed"], false);
+        let message = ret["response"]["status"]["message"].as_str().unwrap();
+        assert!(message.contains("admin is not allowed to skip"));
+    }
+
+    #[actix_web::test]
+    async fn expired_skip_is_flagged() {
+        let mut object = ingress("nginx", None);
+        object["metadata"]["annotations"] = json!({
+            SKIP_ANNOTATION: "true",
+            SKIP_REASON_ANNOTATION: "Legacy client",
+            SKIP_UNTIL_ANNOTATION: "2020-01-01T00:00:00Z"
+        });
+        let ret = call(
+            "/validate",
+            &review(&object),
+            &[
+                "--skip-allowed-users",
+                "admin",
+                "--enforcement-mode",
+                "warn",
+            ],
+        )
+        .await;
+        assert_eq!(ret["response"]["allowed"], true);
+        let warnings = ret["response"]["warnings"].as_array().unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert!(
+            warnings[1]
+                .as_str()
+                .unwrap()
+                .contains("expired at 2020-01-01")
+        );
+        assert_eq!(
+            ret["response"]["auditAnnotations"]["expired-skip"],
+            "2020-01-01T00:00:00+00:00"
+        );
+        assert!(
+            metrics::gather()
+                .unwrap()
+                .contains(r#"ingress_tls_expired_skips_total{kind="Ingress"}"#)
+        );
+    }
+}
@@ -1,7 +1,6 @@
 use std::sync::Arc;
 
 use eyre::Result;
-use futures::{StreamExt, stream};
 use gateway_api::{
     gateways::{Gateway, GatewayListeners},
     httproutes::{HTTPRoute, HTTPRouteParentRefs},
@@ -11,16 +10,25 @@ use ouroboros::self_referencing;
 use tracing::instrument;
 
 #[allow(clippy::wildcard_imports)]
-use crate::helpers::*;
+use crate::{cli::Cli, cluster::Cluster, helpers::*};
 
 #[instrument(skip_all)]
-pub fn validate_httproute<'a>() -> Checks<'a, HTTPRoute, Option<Result<Status>>> {
+pub fn validate_httproute<'a>(
+    conf: &Cli,
+    cluster: &Cluster,
+    requester: &Requester,
+) -> Checks<'a, HTTPRoute, Option<Result<Status>>> {
+    let allow_acme_http01_solvers = !conf.deny_acme_http01_solvers;
+    let cluster = cluster.clone();
     let x: Vec<AsyncClosure<'a, HTTPRoute, Option<Result<Status>>>> = vec![
         // skip
-        Box::new(|httproute| {
+        skip_check(conf, &cluster, requester),
+        // cert-manager ACME HTTP-01 solver
+        Box::new(move |httproute| {
             Box::pin(async move {
-                let skip = get_skip(httproute.as_ref())?;
-                if skip == "true" {
+                if allow_acme_http01_solvers && is_acme_http01_solver_httproute(httproute.as_ref())
+                {
+                    audit_acme_http01_solver(httproute.as_ref());
                     Some(Ok(Status::Allowed))
                 } else {
                     Some(Ok(Status::MoveOn))
@@ -48,37 +56,50 @@ pub fn validate_httproute<'a>() -> Checks<'a, HTTPRoute, Option<Result<Status>>>
             })
         }),
         // attached to http listener
-        Box::new(|httproute| {
+        Box::new(move |httproute| {
+            let cluster = cluster.clone();
             Box::pin(async move {
                 let parentrefs = httproute.spec.parent_refs.as_ref()?;
                 let httproute_namespace = httproute.metadata.namespace.as_ref()?;
-                let result = stream::iter(parentrefs)
-                    .filter_map(|p| async {
-                        filter_gateway_of_http_listener_attached_to(p, httproute_namespace)
-                            .await
-                            .map(|x| x.map(|y| (p.clone(), y)))
+                let glps = parentrefs
+                    .iter()
+                    .filter_map(|p| {
+                        filter_gateway_of_http_listener_attached_to(
+                            p,
+                            httproute_namespace,
+                            &cluster,
+                        )
+                        .map(|y| y.map(|y| (p.clone(), y)))
                     })
-                    .collect::<Vec<_>>()
-                    .await
-                    .into_iter()
                     .collect::<Result<Vec<_>>>();
-                Some(result.map(|glps| {
-                    if glps.is_empty() {
-                        Status::Allowed
-                    } else {
-                        Status::Denied(DenyReason::HTTPRouteNonRedirectAttachedToHTTPListener(glps))
-                    }
-                }))
+                let glps = match glps {
+                    Ok(x) => x,
+                    Err(e) => return Some(Err(e)),
+                };
+                if glps.is_empty() {
+                    Some(Ok(Status::Allowed))
+                } else {
+                    Some(Ok(Status::Denied(
+                        DenyReason::HTTPRouteNonRedirectAttachedToHTTPListener(glps),
+                    )))
+                }
             })
         }),
     ];
-    x.into()
+    Checks::from(x).toggled(conf)
 }
 
 // rewrite httproute to attach to same gateway's https listener, find by hostname, if possible. Or if there is only one.
 #[instrument(skip_all)]
-pub async fn mutate_httproute(httproute: Arc<HTTPRoute>) -> Option<Result<Status>> {
-    let validate_result = validate_httproute().run(httproute.clone()).await?;
+pub async fn mutate_httproute(
+    httproute: Arc<HTTPRoute>,
+    conf: &Cli,
+    cluster: &Cluster,
+    requester: &Requester,
+) -> Option<Result<Status>> {
+    let validate_result = validate_httproute(conf, cluster, requester)
+        .run(httproute.clone())
+        .await?;
     match validate_result {
         Ok(Status::Denied(DenyReason::HTTPRouteNonRedirectAttachedToHTTPListener(
             gateway_listener_pairs,
@@ -171,54 +192,52 @@ pub async fn mutate_httproute(httproute: Arc<HTTPRoute>) -> Option<Result<Status
     }
 }
 
-// TODO: filter on gateway side, the allowed routes
+// The HTTP listeners the parentRef attaches the HTTPRoute to, leaving out
+// the ones whose allowedRoutes do not accept it.
 #[instrument(skip_all)]
-async fn filter_gateway_of_http_listener_attached_to(
+fn filter_gateway_of_http_listener_attached_to(
     p: &HTTPRouteParentRefs,
     httproute_namespace: &str,
+    cluster: &Cluster,
 ) -> Option<Result<GatewayListenerPair>> {
-    if p.kind.as_ref().is_some_and(|x| x == "Gateway") {
-        let gateway = get_gateway(
+    if is_gateway_parentref(p) {
+        let gateway = cluster.get_gateway(
             p.namespace
                 .as_ref()
                 .map_or(httproute_namespace, |s| s.as_str()),
             &p.name,
-        )
-        .await
-        .transpose()?;
-        match gateway {
-            Ok(gateway) => {
-                let gn = gateway.metadata.name.clone()?;
-                let gns = gateway.metadata.namespace.clone()?;
-                let glp = GatewayListenerPairBuilder {
-                    gateway,
-                    listeners_builder: |gateway| {
-                        gateway
-                            .spec
-                            .listeners
-                            .iter()
-                            .filter(|listener| {
-                                listener.protocol == "HTTP"
-                                    && does_parentref_listener_match(
-                                        p,
-                                        listener,
-                                        &gn,
-                                        &gns,
-                                        httproute_namespace,
-                                    )
-                            })
-                            .collect()
-                    },
-                }
-                .build();
-                if glp.borrow_listeners().is_empty() {
-                    None
-                } else {
-                    Some(Ok(glp))
+        )?;
+        let gateway = (*gateway).clone();
+        let gn = gateway.metadata.name.clone()?;
+        let gns = gateway.metadata.namespace.clone()?;
+        let mut attached = vec![];
+        for listener in &gateway.spec.listeners {
+            if listener.protocol == "HTTP"
+                && does_parentref_listener_match(p, listener, &gn, &gns, httproute_namespace)
+            {
+                match does_listener_allow_httproute(listener, &gns, httproute_namespace, cluster) {
+                    Ok(true) => attached.push(listener.name.clone()),
+                    Ok(false) => {}
+                    Err(e) => return Some(Err(e)),
                 }
             }
-            Err(e) => Some(Err(e)),
         }
+        if attached.is_empty() {
+            return None;
+        }
+        let glp = GatewayListenerPairBuilder {
+            gateway,
+            listeners_builder: |gateway| {
+                gateway
+                    .spec
+                    .listeners
+                    .iter()
+                    .filter(|listener| attached.contains(&listener.name))
+                    .collect()
+            },
+        }
+        .build();
+        Some(Ok(glp))
     } else {
         None
     }
@@ -232,3 +251,101 @@ pub struct GatewayListenerPair {
     #[covariant]
     pub listeners: Vec<&'this GatewayListeners>,
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::{
+        cluster::ClusterObjects,
+        testing::{call, call_in, gateway, plain_httproute, review},
+    };
+
+    #[actix_web::test]
+    async fn acme_solver_httproute_is_allowed() {
+        let object = json!({
+            "apiVersion": "gateway.networking.k8s.io/v1",
+            "kind": "HTTPRoute",
+            "metadata": {
+                "name": "cm-acme-http-solver-abcde",
+                "namespace": "test",
+                "labels": {ACME_HTTP01_SOLVER_LABEL: "true"}
+            },
+            "spec": {
+                "parentRefs": [{"name": "whoami", "sectionName": "http"}],
+                "hostnames": ["whoami.magicloud.lan"],
+                "rules": [{
+                    "matches": [{"path": {"type": "Exact", "value": "/.well-known/acme-challenge/xyz"}}],
+                    "backendRefs": [{"name": "cm-acme-http-solver-abcde", "port": 8089}]
+                }]
+            }
+        });
+        let ret = call("/validate", &review(&object), &[]).await;
+        assert_eq!(ret["response"]["allowed"], true);
+    }
+
+    #[actix_web::test]
+    async fn httproute_attached_to_cached_http_listener_is_denied() {
+        let cluster = Cluster::from_objects(ClusterObjects {
+            gateways: vec![serde_json::from_value(gateway()).unwrap()],
+            ..Default::default()
+        });
+        let ret = call_in(cluster, "/validate", &review(&plain_httproute()), &[]).await;
+        assert_eq!(ret["response"]["allowed"], false);
+
+        let ret = call("/validate", &review(&plain_httproute()), &[]).await;
+        assert_eq!(ret["response"]["allowed"], true);
+    }
+
+    #[actix_web::test]
+    async fn allowed_routes_are_honored_both_ways() {
+        let mut httproute = plain_httproute();
+        httproute["metadata"]["namespace"] = json!("team-a");
+        httproute["spec"]["parentRefs"][0]["namespace"] = json!("test");
+        let namespace = json!({
+            "apiVersion": "v1",
+            "kind": "Namespace",
+            "metadata": {"name": "team-a", "labels": {"team": "a"}}
+        });
+        let cases = [
+            ("same namespace by default", Value::Null, true),
+            (
+                "all namespaces",
+                json!({"namespaces": {"from": "All"}}),
+                false,
+            ),
+            (
+                "selected by labels",
+                json!({"namespaces": {"from": "Selector", "selector": {"matchLabels": {"team": "a"}}}}),
+                false,
+            ),
+            (
+                "not selected by expressions",
+                json!({"namespaces": {"from": "Selector", "selector": {
+                    "matchExpressions": [{"key": "team", "operator": "In", "values": ["b"]}]
+                }}}),
+                true,
+            ),
+            (
+                "other kinds only",
+                json!({"namespaces": {"from": "All"}, "kinds": [{"kind": "GRPCRoute"}]}),
+                true,
+            ),
+        ];
+        for (case, allowed_routes, allowed) in cases {
+            let mut gateway = gateway();
+            gateway["spec"]["listeners"][0]["allowedRoutes"] = allowed_routes;
+            let cluster = Cluster::from_objects(ClusterObjects {
+                namespaces: vec![serde_json::from_value(namespace.clone()).unwrap()],
+                gateways: vec![serde_json::from_value(gateway.clone()).unwrap()],
+                httproutes: vec![serde_json::from_value(httproute.clone()).unwrap()],
+                ..Default::default()
+            });
+            let ret = call_in(cluster.clone(), "/validate", &review(&httproute), &[]).await;
+            assert_eq!(ret["response"]["allowed"], allowed, "HTTPRoute, {case}");
+            let ret = call_in(cluster, "/validate", &review(&gateway), &[]).await;
+            assert_eq!(ret["response"]["allowed"], allowed, "Gateway, {case}");
+        }
+    }
+}
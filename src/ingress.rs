@@ -1,4 +1,4 @@
-use std::{collections::BTreeMap, str::FromStr, sync::Arc};
+use std::{collections::BTreeMap, sync::Arc};
 
 use eyre::Result;
 use itertools::Itertools;
@@ -6,16 +6,28 @@ use k8s_openapi::api::networking::v1::{Ingress, IngressTLS};
 use tracing::instrument;
 
 #[allow(clippy::wildcard_imports)]
-use crate::{cli::Cli, helpers::*};
+use crate::{
+    cli::{CertManagerAnnotations, Cli},
+    cluster::Cluster,
+    helpers::*,
+    naming::NameVariables,
+};
 
 #[instrument(skip_all)]
-pub fn validate_ingress<'a>() -> Checks<'a, Ingress, Option<Result<Status>>> {
+pub fn validate_ingress<'a>(
+    conf: &Cli,
+    cluster: &Cluster,
+    requester: &Requester,
+) -> Checks<'a, Ingress, Option<Result<Status>>> {
+    let allow_acme_http01_solvers = !conf.deny_acme_http01_solvers;
     let x: Vec<AsyncClosure<'a, Ingress, Option<Result<Status>>>> = vec![
         // skip
-        Box::new(|ingress| {
+        skip_check(conf, cluster, requester),
+        // cert-manager ACME HTTP-01 solver
+        Box::new(move |ingress| {
             Box::pin(async move {
-                let skip = get_skip(ingress.as_ref())?;
-                if skip == "true" {
+                if allow_acme_http01_solvers && is_acme_http01_solver_ingress(ingress.as_ref()) {
+                    audit_acme_http01_solver(ingress.as_ref());
                     Some(Ok(Status::Allowed))
                 } else {
                     Some(Ok(Status::MoveOn))
@@ -38,56 +50,147 @@ pub fn validate_ingress<'a>() -> Checks<'a, Ingress, Option<Result<Status>>> {
                 }
             })
         }),
+        // every host covered by TLS
+        Box::new(|ingress| {
+            Box::pin(async move {
+                let uncovered = get_uncovered_hosts(ingress.as_ref());
+                if uncovered.is_empty() {
+                    Some(Ok(Status::MoveOn))
+                } else {
+                    Some(Ok(Status::Denied(DenyReason::IngressHostsNotCoveredByTLS(
+                        uncovered,
+                    ))))
+                }
+            })
+        }),
     ];
-    x.into()
+    Checks::from(x).toggled(conf)
 }
 
 #[instrument(skip_all)]
-pub async fn mutate_ingress(ingress: Arc<Ingress>, conf: &Cli) -> Option<Result<Status>> {
-    let validate_result = validate_ingress().run(ingress.clone()).await?;
-    if matches!(
-        validate_result,
-        Ok(Status::Denied(DenyReason::IngressNoTLS))
-    ) {
-        let name = ingress.metadata.name.as_ref()?;
-        let ns = ingress.metadata.namespace.as_ref()?;
-        let ic =
-            SupportedIngressClass::from_str(ingress.spec.as_ref()?.ingress_class_name.as_ref()?);
-        let mut hosts = ingress
-            .spec
-            .as_ref()?
-            .rules
-            .as_ref()?
-            .iter()
-            .filter_map(|x| x.host.clone())
-            .collect::<Vec<_>>();
-        if let Some(edns) = get_external_dns_hostname(ingress.as_ref()) {
-            hosts.extend(edns.into_iter());
-        }
-        let hosts = hosts.into_iter().unique().collect::<Vec<_>>();
-        let ret = if hosts.is_empty() {
-            Ok(Status::Invalid(
-                "The Ingress does not contain hosts information".to_string(),
-            ))
-        } else {
-            let tls = vec![IngressTLS {
-                hosts: Some(hosts),
-                secret_name: Some(format!("{name}-tls")),
-            }];
-            let mut target = (*ingress).clone();
-            let mut annotations = target.metadata.annotations.take().unwrap_or_default();
-            if let Some(s) = target.spec.as_mut() {
-                s.tls = Some(tls);
+pub async fn mutate_ingress(
+    ingress: Arc<Ingress>,
+    conf: &Cli,
+    cluster: &Cluster,
+    requester: &Requester,
+) -> Option<Result<Status>> {
+    let validate_result = validate_ingress(conf, cluster, requester)
+        .run(ingress.clone())
+        .await?;
+    let missing_hosts = match validate_result {
+        Ok(Status::Denied(DenyReason::IngressNoTLS)) => get_hosts(ingress.as_ref()),
+        Ok(Status::Denied(DenyReason::IngressHostsNotCoveredByTLS(hosts))) => hosts,
+        _ => return Some(validate_result),
+    };
+    let name = ingress.metadata.name.as_ref()?;
+    let ns = ingress.metadata.namespace.as_ref()?;
+    let ic = resolve_ingress_class(ingress.as_ref(), cluster);
+    let hosts = get_hosts(ingress.as_ref());
+    let issuer = match conf
+        .resolve_issuer(&ingress.metadata, &hosts, cluster)
+        .await
+    {
+        Ok(x) => x,
+        Err(reason) => return Some(Ok(Status::Denied(reason))),
+    };
+    let ret = if missing_hosts.is_empty() {
+        Ok(Status::Invalid(
+            "The Ingress does not contain hosts information".to_string(),
+        ))
+    } else {
+        let mut target = (*ingress).clone();
+        let mut annotations = target.metadata.annotations.take().unwrap_or_default();
+        if let Some(s) = target.spec.as_mut() {
+            let secret_name = conf.policy.templates.ingress_secret(&NameVariables {
+                namespace: ns,
+                name,
+                listener: "",
+                host: hosts.first().map_or("", String::as_str),
+            });
+            let tls = s.tls.get_or_insert_with(Vec::new);
+            // Extend the entry we made before, instead of requesting another
+            // certificate into the same secret.
+            if let Some(t) = tls
+                .iter_mut()
+                .find(|t| t.secret_name.as_ref() == Some(&secret_name))
+            {
+                t.hosts.get_or_insert_with(Vec::new).extend(missing_hosts);
+            } else {
+                tls.push(IngressTLS {
+                    hosts: Some(missing_hosts),
+                    secret_name: Some(secret_name),
+                });
             }
-            patch_annotations(&mut annotations, &ic, ns, conf);
-            target.metadata.annotations = Some(annotations);
+        }
+        patch_annotations(&mut annotations, &ic, ns, conf, issuer.as_ref());
+        target.metadata.annotations = Some(annotations);
 
-            patch(ingress.as_ref(), &target).map(Status::Patch)
-        };
-        Some(ret)
-    } else {
-        Some(validate_result)
+        patch(ingress.as_ref(), &target).map(Status::Patch)
+    };
+    Some(ret)
+}
+
+fn is_acme_http01_solver_ingress(ingress: &Ingress) -> bool {
+    is_acme_http01_solver(ingress)
+        && ingress
+            .spec
+            .as_ref()
+            .and_then(|s| s.rules.as_ref())
+            .is_some_and(|rules| {
+                !rules.is_empty()
+                    && rules.iter().all(|rule| {
+                        rule.http.as_ref().is_some_and(|http| {
+                            !http.paths.is_empty()
+                                && http.paths.iter().all(|p| {
+                                    p.path
+                                        .as_ref()
+                                        .is_some_and(|x| x.starts_with(ACME_CHALLENGE_PATH_PREFIX))
+                                })
+                        })
+                    })
+            })
+}
+
+// Hosts of rules, and hosts to be published by external-dns.
+fn get_hosts(ingress: &Ingress) -> Vec<String> {
+    let mut hosts = ingress
+        .spec
+        .as_ref()
+        .and_then(|s| s.rules.as_ref())
+        .map(|rules| {
+            rules
+                .iter()
+                .filter_map(|x| x.host.clone())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    if let Some(edns) = get_external_dns_hostname(ingress) {
+        hosts.extend(edns);
     }
+    hosts
+        .iter()
+        .map(|x| normalize_hostname(x))
+        .filter(|x| !x.is_empty())
+        .unique()
+        .collect()
+}
+
+fn get_uncovered_hosts(ingress: &Ingress) -> Vec<String> {
+    let tls_hosts = ingress
+        .spec
+        .as_ref()
+        .and_then(|s| s.tls.as_ref())
+        .map(|tls| {
+            tls.iter()
+                .filter_map(|t| t.hosts.as_ref())
+                .flatten()
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    get_hosts(ingress)
+        .into_iter()
+        .filter(|h| !tls_hosts.iter().any(|t| does_hostname_cover(t, h)))
+        .collect()
 }
 
 #[instrument(skip_all)]
@@ -96,8 +199,9 @@ fn patch_annotations(
     ic: &Result<SupportedIngressClass>,
     ns: &str,
     conf: &Cli,
+    cma: Option<&CertManagerAnnotations>,
 ) {
-    if let Some(ref x) = conf.cma {
+    if let Some(x) = cma {
         if let Some(ref group) = x.group {
             annotations
                 .entry(ISSUER_GROUP.to_string())
@@ -142,3 +246,162 @@ fn patch_annotations(
         tracing::warn!(target: "patch-annotation-error", message = format!("{ic:?}"));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::testing::{apply, call, ingress, mutate, review};
+
+    #[actix_web::test]
+    async fn mutate_nginx_ingress_without_tls() {
+        let object = ingress("nginx", None);
+        let review = mutate(&object).await;
+        let response = &review["response"];
+        assert_eq!(response["uid"], "705ab4f5-6393-11e8-b7cc-42010a800002");
+        assert_eq!(response["allowed"], true);
+
+        let target = apply(&object, response);
+        assert_eq!(
+            target["spec"]["tls"],
+            json!([{"hosts": ["whoami.magicloud.lan"], "secretName": "whoami-tls"}])
+        );
+        assert_eq!(
+            target["metadata"]["annotations"],
+            json!({
+                CLUSTER_ISSUER: "letsencrypt",
+                NGINX_FORCE_SSL_REDIRECT: "true",
+            })
+        );
+        assert_eq!(target["spec"]["rules"], object["spec"]["rules"]);
+    }
+
+    #[actix_web::test]
+    async fn mutate_traefik_ingress_without_tls() {
+        let object = ingress("traefik", None);
+        let review = mutate(&object).await;
+        let response = &review["response"];
+        assert_eq!(response["allowed"], true);
+
+        let target = apply(&object, response);
+        assert_eq!(
+            target["metadata"]["annotations"][TRAEFIK_MIDDLEWARE_ANNOTATION],
+            "test-https-redirect@kubernetescrd"
+        );
+        assert_eq!(target["spec"]["tls"][0]["secretName"], "whoami-tls");
+    }
+
+    #[actix_web::test]
+    async fn mutate_ingress_with_tls() {
+        let object = ingress(
+            "nginx",
+            Some(json!([{"hosts": ["whoami.magicloud.lan"], "secretName": "whoami"}])),
+        );
+        let review = mutate(&object).await;
+        let response = &review["response"];
+        assert_eq!(response["allowed"], true);
+        assert!(response.get("patch").is_none());
+        assert!(response.get("patchType").is_none());
+    }
+
+    #[actix_web::test]
+    async fn mutate_ingress_with_legacy_class_annotation() {
+        let mut object = ingress("traefik", None);
+        object["spec"]
+            .as_object_mut()
+            .unwrap()
+            .remove("ingressClassName");
+        object["metadata"]["annotations"] = json!({INGRESS_CLASS_ANNOTATION: "traefik"});
+        let review = mutate(&object).await;
+        let response = &review["response"];
+        assert_eq!(response["allowed"], true);
+
+        let target = apply(&object, response);
+        assert_eq!(
+            target["metadata"]["annotations"][TRAEFIK_MIDDLEWARE_ANNOTATION],
+            "test-https-redirect@kubernetescrd"
+        );
+        assert_eq!(target["spec"]["tls"][0]["secretName"], "whoami-tls");
+    }
+
+    fn partially_covered_ingress() -> Value {
+        let mut object = ingress(
+            "nginx",
+            Some(json!([{"hosts": ["*.magicloud.lan"], "secretName": "wildcard"}])),
+        );
+        object["metadata"]["annotations"] = json!({
+            "external-dns.alpha.kubernetes.io/hostname": "whoami.magicloud.lan.,whoami.magiclouds.cn."
+        });
+        object["spec"]["rules"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({"host": "a.b.magicloud.lan"}));
+        object
+    }
+
+    #[actix_web::test]
+    async fn validate_ingress_lists_uncovered_hosts() {
+        let ret = call("/validate", &review(&partially_covered_ingress()), &[]).await;
+        assert_eq!(ret["response"]["allowed"], false);
+        let message = ret["response"]["status"]["message"].as_str().unwrap();
+        assert!(message.ends_with("a.b.magicloud.lan, whoami.magiclouds.cn"));
+    }
+
+    #[actix_web::test]
+    async fn mutate_ingress_adds_missing_hosts() {
+        let object = partially_covered_ingress();
+        let review = mutate(&object).await;
+        let response = &review["response"];
+        assert_eq!(response["allowed"], true);
+
+        let target = apply(&object, response);
+        assert_eq!(
+            target["spec"]["tls"],
+            json!([
+                {"hosts": ["*.magicloud.lan"], "secretName": "wildcard"},
+                {"hosts": ["a.b.magicloud.lan", "whoami.magiclouds.cn"], "secretName": "whoami-tls"},
+            ])
+        );
+    }
+
+    fn acme_solver_ingress(path: &str) -> Value {
+        let mut object = ingress("nginx", None);
+        object["metadata"]["labels"] = json!({ACME_HTTP01_SOLVER_LABEL: "true"});
+        object["metadata"]["ownerReferences"] = json!([{
+            "apiVersion": "acme.cert-manager.io/v1",
+            "kind": "Challenge",
+            "name": "whoami-tls-1-2-3",
+            "uid": "0e8dd3e5-4a2c-4b8e-9d3b-4e3f8f6e2a10"
+        }]);
+        object["spec"]["rules"][0]["http"]["paths"][0]["path"] = json!(path);
+        object["spec"]["rules"][0]["http"]["paths"][0]["pathType"] =
+            json!("ImplementationSpecific");
+        object
+    }
+
+    #[actix_web::test]
+    async fn acme_solver_ingress_is_allowed() {
+        let object = acme_solver_ingress("/.well-known/acme-challenge/xyz");
+        for uri in ["/validate", "/mutate"] {
+            let ret = call(uri, &review(&object), &[]).await;
+            assert_eq!(ret["response"]["allowed"], true);
+            assert!(ret["response"].get("patch").is_none());
+        }
+
+        let ret = call(
+            "/validate",
+            &review(&object),
+            &["--deny-acme-http01-solvers"],
+        )
+        .await;
+        assert_eq!(ret["response"]["allowed"], false);
+    }
+
+    #[actix_web::test]
+    async fn acme_solver_label_needs_challenge_path() {
+        let object = acme_solver_ingress("/");
+        let ret = call("/validate", &review(&object), &[]).await;
+        assert_eq!(ret["response"]["allowed"], false);
+    }
+}
@@ -0,0 +1,133 @@
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use eyre::Result;
+use k8s_openapi::{
+    api::coordination::v1::{Lease, LeaseSpec},
+    apimachinery::pkg::apis::meta::v1::MicroTime,
+};
+use kube::{
+    Api, Client,
+    api::{ObjectMeta, PostParams},
+};
+use tracing::instrument;
+
+use crate::metrics::KUBE_API_DURATION;
+
+const LEASE_DURATION: Duration = Duration::from_secs(15);
+const RETRY_PERIOD: Duration = Duration::from_secs(5);
+
+// A `coordination.k8s.io` Lease, in the namespace of the service account,
+// electing the one replica running what must not run in all of them.
+pub struct LeaderLease {
+    api: Api<Lease>,
+    name: String,
+    holder: String,
+}
+impl LeaderLease {
+    pub fn new(client: Client, name: &str) -> Self {
+        let namespace = client.default_namespace().to_string();
+        Self {
+            api: Api::namespaced(client, &namespace),
+            name: name.to_string(),
+            // The pod name.
+            holder: std::env::var("HOSTNAME")
+                .unwrap_or_else(|_| format!("{}-{}", env!("CARGO_PKG_NAME"), std::process::id())),
+        }
+    }
+
+    // Returns once this replica holds the lease.
+    #[instrument(skip_all)]
+    pub async fn acquire(&self) {
+        loop {
+            match self.try_acquire().await {
+                Ok(true) => {
+                    tracing::info!(target: "leader-lease", message = format!("{} acquired lease {}", self.holder, self.name));
+                    return;
+                }
+                Ok(false) => {}
+                Err(e) => tracing::warn!(target: "leader-lease", message = format!("{e}")),
+            }
+            smol::Timer::after(RETRY_PERIOD).await;
+        }
+    }
+
+    // Renews the lease, returning once it is lost, or could not be renewed
+    // before it expires.
+    #[instrument(skip_all)]
+    pub async fn keep(&self) {
+        let mut renewed = Instant::now();
+        loop {
+            smol::Timer::after(RETRY_PERIOD).await;
+            match self.try_acquire().await {
+                Ok(true) => renewed = Instant::now(),
+                Ok(false) => break,
+                Err(e) => {
+                    tracing::warn!(target: "leader-lease", message = format!("{e}"));
+                    if renewed.elapsed() + RETRY_PERIOD >= LEASE_DURATION {
+                        break;
+                    }
+                }
+            }
+        }
+        tracing::info!(target: "leader-lease", message = format!("{} lost lease {}", self.holder, self.name));
+    }
+
+    // Takes the lease when it is free or expired, renews it when held.
+    // Conflicting writes of other replicas fail by the resource version.
+    async fn try_acquire(&self) -> Result<bool> {
+        let _timer = KUBE_API_DURATION
+            .with_label_values(&["lease"])
+            .start_timer();
+        let now = Utc::now();
+        let duration = i32::try_from(LEASE_DURATION.as_secs()).unwrap_or(i32::MAX);
+        let current = self.api.get_opt(&self.name).await?;
+        let spec = current
+            .as_ref()
+            .and_then(|x| x.spec.clone())
+            .unwrap_or_default();
+        let held = spec.holder_identity.as_ref() == Some(&self.holder);
+        let expired = spec.renew_time.as_ref().is_none_or(|t| {
+            t.0 + chrono::Duration::seconds(spec.lease_duration_seconds.unwrap_or(duration).into())
+                < now
+        });
+        if !held && !expired {
+            return Ok(false);
+        }
+        let spec = LeaseSpec {
+            holder_identity: Some(self.holder.clone()),
+            lease_duration_seconds: Some(duration),
+            acquire_time: if held {
+                spec.acquire_time
+            } else {
+                Some(MicroTime(now))
+            },
+            renew_time: Some(MicroTime(now)),
+            lease_transitions: if held || current.is_none() {
+                spec.lease_transitions
+            } else {
+                Some(spec.lease_transitions.unwrap_or_default() + 1)
+            },
+        };
+        let ret = if let Some(mut lease) = current {
+            lease.spec = Some(spec);
+            self.api
+                .replace(&self.name, &PostParams::default(), &lease)
+                .await
+        } else {
+            let lease = Lease {
+                metadata: ObjectMeta {
+                    name: Some(self.name.clone()),
+                    ..Default::default()
+                },
+                spec: Some(spec),
+            };
+            self.api.create(&PostParams::default(), &lease).await
+        };
+        match ret {
+            Ok(_) => Ok(true),
+            Err(kube::Error::Api(e)) if e.code == 409 => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -10,14 +10,29 @@
 #![allow(clippy::multiple_crate_versions)]
 #![allow(clippy::wildcard_dependencies)]
 
+mod audit;
+mod check;
 mod cli;
+mod cluster;
+mod controller;
+mod fix;
 mod gateway;
 mod helpers;
 mod httproute;
 mod ingress;
+mod lease;
+mod metrics;
+mod naming;
+mod policy;
+mod probes;
+mod report;
+#[cfg(test)]
+mod testing;
 mod tls_cert_resolver;
 mod webhook;
 
+use std::process::ExitCode;
+
 use clap::Parser;
 use eyre::Result;
 use mimalloc::MiMalloc;
@@ -34,18 +49,18 @@ use tracing_subscriber::{
 };
 
 #[allow(clippy::wildcard_imports)]
-use crate::cli::Cli;
+use crate::cli::{Cli, Command};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
 // This usage seems breaking analyzer.
 // #[macro_rules_attribute::apply(smol_macros::main!)]
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     smol::block_on(async_compat::Compat::new(real_main()))
 }
 
-async fn real_main() -> Result<()> {
+async fn real_main() -> Result<ExitCode> {
     rustls::crypto::aws_lc_rs::default_provider()
         .install_default()
         .expect("Cannot initialize AWS LC");
@@ -105,7 +120,26 @@ async fn real_main() -> Result<()> {
     color_eyre::install()?;
 
     let cli = Cli::parse();
-    cli.start().await?;
+    // The subcommands read the policy file once, the webhook watches it.
+    let conf = cli.with_policy_file()?;
+    match cli.command.clone() {
+        Some(Command::Check(args)) => {
+            if !conf.check(&args).await? {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Some(Command::Audit(args)) => {
+            if !conf.audit(&args).await? {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Some(Command::Fix(args)) => {
+            if !conf.fix(&args).await? {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        None => cli.start().await?,
+    }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,185 @@
+use std::{sync::LazyLock, time::Duration};
+
+use eyre::Result;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+use crate::{
+    cli::EnforcementMode,
+    helpers::{DenyReason, Status},
+};
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+static ADMISSION_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "ingress_tls_admission_requests_total",
+            "Admission requests processed",
+        ),
+        &["endpoint", "kind", "operation", "outcome"],
+    ))
+});
+
+static DENY_REASONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "ingress_tls_deny_reasons_total",
+            "Admission requests denied, by reason",
+        ),
+        &["endpoint", "reason"],
+    ))
+});
+
+static ADMISSION_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "ingress_tls_admission_duration_seconds",
+            "Time taken to process admission requests",
+        ),
+        &["endpoint", "kind"],
+    ))
+});
+
+static EXPIRED_SKIPS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "ingress_tls_expired_skips_total",
+            "Admission requests carrying an expired skip annotation",
+        ),
+        &["kind"],
+    ))
+});
+
+static COMPLIANT_OBJECTS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new(
+            "ingress_tls_compliant_objects",
+            "Existing objects passing the checks, as of the last audit",
+        ),
+        &["namespace", "kind"],
+    ))
+});
+
+static NONCOMPLIANT_OBJECTS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new(
+            "ingress_tls_noncompliant_objects",
+            "Existing objects failing the checks, as of the last audit",
+        ),
+        &["namespace", "kind"],
+    ))
+});
+
+static POLICY_RELOADS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "ingress_tls_policy_reloads_total",
+            "Policy file reloads, by outcome",
+        ),
+        &["outcome"],
+    ))
+});
+
+// Only the calls to the API server, lookups in the cluster cache are not
+// timed.
+pub static KUBE_API_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "ingress_tls_kube_api_duration_seconds",
+            "Time taken by calls to the Kubernetes API server",
+        ),
+        &["call"],
+    ))
+});
+
+// The metric definitions are static, they only fail on programming errors.
+fn register<T: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<T>) -> T {
+    let metric = metric.expect("Invalid metric definition");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Duplicated metric definition");
+    metric
+}
+
+pub fn observe_admission(
+    endpoint: &str,
+    kind: &str,
+    operation: &str,
+    status: &Status,
+    mode: EnforcementMode,
+    elapsed: Duration,
+) {
+    let outcome = match status {
+        Status::MoveOn | Status::Allowed => "allowed",
+        Status::Denied(DenyReason::InternalError(_)) => "error",
+        Status::Denied(reason) => {
+            DENY_REASONS
+                .with_label_values(&[endpoint, reason.name()])
+                .inc();
+            match mode {
+                EnforcementMode::Enforce => "denied",
+                EnforcementMode::Warn => "warned",
+                EnforcementMode::Audit => "audited",
+            }
+        }
+        Status::Invalid(_) => "invalid",
+        Status::Patch(_) => "patched",
+    };
+    ADMISSION_REQUESTS
+        .with_label_values(&[endpoint, kind, operation, outcome])
+        .inc();
+    ADMISSION_DURATION
+        .with_label_values(&[endpoint, kind])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn observe_expired_skip(kind: &str) {
+    EXPIRED_SKIPS.with_label_values(&[kind]).inc();
+}
+
+pub fn set_compliance(namespace: &str, kind: &str, compliant: usize, noncompliant: usize) {
+    COMPLIANT_OBJECTS
+        .with_label_values(&[namespace, kind])
+        .set(i64::try_from(compliant).unwrap_or(i64::MAX));
+    NONCOMPLIANT_OBJECTS
+        .with_label_values(&[namespace, kind])
+        .set(i64::try_from(noncompliant).unwrap_or(i64::MAX));
+}
+
+pub fn observe_policy_reload(succeeded: bool) {
+    POLICY_RELOADS
+        .with_label_values(&[if succeeded { "succeeded" } else { "rejected" }])
+        .inc();
+}
+
+// No more objects of the kind in the namespace.
+pub fn remove_compliance(namespace: &str, kind: &str) {
+    let _ = COMPLIANT_OBJECTS.remove_label_values(&[namespace, kind]);
+    let _ = NONCOMPLIANT_OBJECTS.remove_label_values(&[namespace, kind]);
+}
+
+// The request could not be processed at all.
+pub fn observe_admission_error(endpoint: &str, kind: &str, operation: &str, elapsed: Duration) {
+    ADMISSION_REQUESTS
+        .with_label_values(&[endpoint, kind, operation, "error"])
+        .inc();
+    ADMISSION_DURATION
+        .with_label_values(&[endpoint, kind])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn gather() -> Result<String> {
+    LazyLock::force(&ADMISSION_REQUESTS);
+    LazyLock::force(&DENY_REASONS);
+    LazyLock::force(&ADMISSION_DURATION);
+    LazyLock::force(&EXPIRED_SKIPS);
+    LazyLock::force(&COMPLIANT_OBJECTS);
+    LazyLock::force(&NONCOMPLIANT_OBJECTS);
+    LazyLock::force(&POLICY_RELOADS);
+    LazyLock::force(&KUBE_API_DURATION);
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
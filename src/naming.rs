@@ -0,0 +1,241 @@
+use std::fmt::Write;
+
+use eyre::{Result, eyre};
+use serde::Deserialize;
+
+// Secret names are DNS subdomains.
+pub const SUBDOMAIN_MAX: usize = 253;
+// Listener names end up in the names of what the Gateway implementations
+// generate, keep them to a DNS label.
+pub const LABEL_MAX: usize = 63;
+// Dash and 8 hex digits.
+const HASH_SUFFIX_LEN: usize = 9;
+
+// What a name template can refer to. Unset ones render empty.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NameVariables<'a> {
+    pub namespace: &'a str,
+    // Of the Ingress or Gateway.
+    pub name: &'a str,
+    pub listener: &'a str,
+    // The first host of the Ingress, or the hostname of the listener.
+    pub host: &'a str,
+}
+impl NameVariables<'_> {
+    fn get(&self, variable: &str) -> String {
+        match variable {
+            "namespace" => self.namespace.to_string(),
+            "name" | "gateway" => self.name.to_string(),
+            "listener" => self.listener.to_string(),
+            "host" => self.host.trim_end_matches('.').replace('*', "wildcard"),
+            "hash" => short_hash(&format!(
+                "{}/{}/{}/{}",
+                self.namespace, self.name, self.listener, self.host
+            )),
+            _ => String::new(),
+        }
+    }
+}
+
+// `{variable}` is replaced by the value of the variable. Rendered names are
+// lowercased, and characters not allowed in names become dashes.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct NameTemplate(String);
+impl NameTemplate {
+    pub fn new(template: &str) -> Self {
+        Self(template.to_string())
+    }
+
+    // Too long names are cut, and suffixed with a hash of the full name to
+    // keep them apart.
+    pub fn render(&self, variables: &NameVariables<'_>, max: usize) -> String {
+        fit(&sanitize(&self.expand(variables)), max)
+    }
+
+    // For DNS labels, such as listener names, dots become dashes too.
+    pub fn render_label(&self, variables: &NameVariables<'_>) -> String {
+        fit(
+            &sanitize(&self.expand(variables)).replace('.', "-"),
+            LABEL_MAX,
+        )
+    }
+
+    fn expand(&self, variables: &NameVariables<'_>) -> String {
+        let mut name = String::new();
+        let mut rest = self.0.as_str();
+        while let Some(start) = rest.find('{') {
+            name.push_str(&rest[..start]);
+            let end = rest[start..].find('}').map_or(rest.len(), |x| start + x);
+            name.push_str(&variables.get(&rest[start + 1..end]));
+            rest = rest.get(end + 1..).unwrap_or_default();
+        }
+        name.push_str(rest);
+        name
+    }
+
+    // Variables are checked against the ones the template is rendered with,
+    // and the literal parts against the characters allowed in names.
+    pub fn validate(&self, variables: &[&str]) -> Result<()> {
+        self.validate_chars(variables, is_name_char)
+    }
+
+    // As `validate`, for the templates rendered by `render_label`.
+    pub fn validate_label(&self, variables: &[&str]) -> Result<()> {
+        self.validate_chars(variables, |c| c != '.' && is_name_char(c))
+    }
+
+    fn validate_chars(&self, variables: &[&str], is_allowed: fn(char) -> bool) -> Result<()> {
+        let mut rest = self.0.as_str();
+        let mut literal = String::new();
+        while let Some(start) = rest.find('{') {
+            literal.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| eyre!("Unclosed variable in template {}", self.0))?;
+            let variable = &rest[start + 1..start + end];
+            if !variables.contains(&variable) {
+                return Err(eyre!(
+                    "Unknown variable {variable} in template {}, expecting one of {}",
+                    self.0,
+                    variables.join(", ")
+                ));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        literal.push_str(rest);
+        if let Some(c) = literal.chars().find(|c| !is_allowed(*c)) {
+            return Err(eyre!(
+                "Character {c:?} is not allowed in names, in template {}",
+                self.0
+            ));
+        }
+        if self.0.trim().is_empty() {
+            return Err(eyre!("Empty template"));
+        }
+        Ok(())
+    }
+}
+
+// Cuts the name to `max` characters, keeping it unique with a hash of the
+// whole.
+pub fn fit(name: &str, max: usize) -> String {
+    if name.len() <= max {
+        return name.to_string();
+    }
+    let prefix = name[..max - HASH_SUFFIX_LEN].trim_end_matches(['-', '.']);
+    format!("{prefix}-{}", short_hash(name))
+}
+
+fn sanitize(name: &str) -> String {
+    let sanitized = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if is_name_char(c) { c } else { '-' })
+        .collect::<String>();
+    let sanitized = sanitized.trim_matches(['-', '.']);
+    if sanitized.is_empty() {
+        short_hash(name)
+    } else {
+        sanitized.to_string()
+    }
+}
+
+const fn is_name_char(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.'
+}
+
+// FNV-1a, stable across builds, unlike the std hasher.
+pub fn short_hash(s: &str) -> String {
+    let hash = s.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    let mut ret = String::new();
+    let _ = write!(ret, "{:08x}", hash & 0xffff_ffff);
+    ret
+}
+
+#[cfg(test)]
+// Templates look like format strings.
+#[allow(clippy::literal_string_with_formatting_args)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_render_within_limits() {
+        let variables = NameVariables {
+            namespace: "team-a",
+            name: "whoami",
+            listener: "http",
+            host: "*.Magicloud.lan.",
+        };
+        assert_eq!(
+            NameTemplate::new("{namespace}-{name}-{listener}-tls").render(&variables, LABEL_MAX),
+            "team-a-whoami-http-tls"
+        );
+        assert_eq!(
+            NameTemplate::new("{host}").render(&variables, SUBDOMAIN_MAX),
+            "wildcard.magicloud.lan"
+        );
+        let hash = NameTemplate::new("{hash}").render(&variables, LABEL_MAX);
+        assert_eq!(hash.len(), 8);
+        assert_eq!(
+            hash,
+            NameTemplate::new("{hash}").render(&variables, LABEL_MAX)
+        );
+
+        let long = NameVariables {
+            name: &"a".repeat(80),
+            ..variables
+        };
+        let template = NameTemplate::new("{name}-https");
+        let name = template.render(&long, LABEL_MAX);
+        assert_eq!(name.len(), LABEL_MAX);
+        assert!(name.starts_with("aaaa"));
+        let other = template.render(
+            &NameVariables {
+                name: &"a".repeat(81),
+                ..variables
+            },
+            LABEL_MAX,
+        );
+        assert_ne!(name, other);
+
+        let template = NameTemplate::new("{host}-https");
+        assert_eq!(
+            template.render_label(&variables),
+            "wildcard-magicloud-lan-https"
+        );
+        let name = template.render_label(&NameVariables {
+            host: &format!("{}.lan", "a".repeat(80)),
+            ..variables
+        });
+        assert_eq!(name.len(), LABEL_MAX);
+        assert!(!name.contains('.'));
+    }
+
+    #[test]
+    fn templates_are_validated() {
+        let variables = ["namespace", "name", "hash"];
+        assert!(
+            NameTemplate::new("{namespace}-{name}-tls")
+                .validate(&variables)
+                .is_ok()
+        );
+        for template in ["{listener}-tls", "{name", "{name}_tls", "TLS-{name}", ""] {
+            assert!(
+                NameTemplate::new(template).validate(&variables).is_err(),
+                "{template}"
+            );
+        }
+        assert!(
+            NameTemplate::new("{name}.https")
+                .validate(&variables)
+                .is_ok()
+        );
+        assert!(
+            NameTemplate::new("{name}.https")
+                .validate_label(&variables)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,976 @@
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+
+use eyre::{Result, eyre};
+use gateway_api::gateways::{
+    GatewayListeners, GatewayListenersAllowedRoutes, GatewayListenersAllowedRoutesNamespaces,
+    GatewayListenersAllowedRoutesNamespacesFrom, GatewayListenersTlsMode,
+};
+use inotify::{Inotify, WatchMask};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use regex::Regex;
+use serde::Deserialize;
+use smol::{Task, lock::RwLock, unblock};
+use tracing::instrument;
+
+use crate::{
+    cli::{CertManagerAnnotations, Cli, EnforcementMode, parse_issuer},
+    cluster::Cluster,
+    helpers::{CLUSTER_ISSUER, DenyReason, ISSUER, Rule, get_namespace_issuer},
+    metrics,
+    naming::{NameTemplate, NameVariables, SUBDOMAIN_MAX},
+};
+
+// Settings only the policy file carries.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    pub exempt_namespaces: Vec<String>,
+    // Rule name -> enabled. Rules not listed are enabled.
+    pub checks: BTreeMap<String, bool>,
+    pub templates: Templates,
+    // Keyed on GatewayClass name.
+    pub gateway_classes: BTreeMap<String, GatewayClassPolicy>,
+    // Keyed on the controller name of GatewayClass-es, for the ones not
+    // listed by name.
+    pub gateway_controllers: BTreeMap<String, GatewayClassPolicy>,
+    // Ordered, the first matching one wins. Hosts not matching any use the
+    // default issuer.
+    pub issuer_rules: Vec<IssuerRule>,
+    pub listener_certificates: ListenerCertificates,
+}
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            exempt_namespaces: vec![],
+            checks: BTreeMap::new(),
+            templates: Templates::default(),
+            gateway_classes: BTreeMap::from([(
+                "traefik".to_string(),
+                GatewayClassPolicy::traefik(),
+            )]),
+            gateway_controllers: BTreeMap::from([(
+                "traefik.io/gateway-controller".to_string(),
+                GatewayClassPolicy::traefik(),
+            )]),
+            issuer_rules: vec![],
+            listener_certificates: ListenerCertificates::default(),
+        }
+    }
+}
+impl Policy {
+    pub fn is_check_enabled(&self, rule: &str) -> bool {
+        self.checks.get(rule).copied().unwrap_or(true)
+    }
+
+    pub fn is_exempt(&self, namespace: &str) -> bool {
+        self.exempt_namespaces.iter().any(|x| x == namespace)
+    }
+
+    // By the name of the GatewayClass, then by its controller.
+    pub fn gateway_class(
+        &self,
+        gateway_class: &str,
+        controller_name: Option<&str>,
+    ) -> GatewayClassPolicy {
+        self.gateway_classes
+            .get(gateway_class)
+            .or_else(|| self.gateway_controllers.get(controller_name?))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+// Whether the HTTPS listeners added to a Gateway share one certificate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerCertificates {
+    #[default]
+    Shared,
+    Own,
+}
+
+#[derive(Debug, Clone)]
+pub struct IssuerRule {
+    hosts: HostPattern,
+    pub issuer: CertManagerAnnotations,
+}
+impl IssuerRule {
+    fn matches(&self, host: &str) -> bool {
+        match &self.hosts {
+            HostPattern::Suffix(suffix) => host == suffix || host.ends_with(&format!(".{suffix}")),
+            HostPattern::Regex(regex) => regex.is_match(host),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum HostPattern {
+    // Without the leading dot. `lan` matches `lan` and `whoami.lan`.
+    Suffix(String),
+    Regex(Regex),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct IssuerRuleFile {
+    host_suffix: Option<String>,
+    host_regex: Option<String>,
+    issuer: String,
+    issuer_kind: Option<String>,
+    issuer_group: Option<String>,
+}
+impl TryFrom<IssuerRuleFile> for IssuerRule {
+    type Error = eyre::Report;
+
+    fn try_from(value: IssuerRuleFile) -> Result<Self> {
+        let hosts = match (value.host_suffix, value.host_regex) {
+            (Some(suffix), None) => HostPattern::Suffix(
+                suffix
+                    .trim_start_matches('.')
+                    .trim_end_matches('.')
+                    .to_lowercase(),
+            ),
+            (None, Some(regex)) => HostPattern::Regex(Regex::new(&regex)?),
+            _ => {
+                return Err(eyre!(
+                    "Issuer rule of {} needs either hostSuffix or hostRegex",
+                    value.issuer
+                ));
+            }
+        };
+        Ok(Self {
+            hosts,
+            issuer: CertManagerAnnotations {
+                issuer: parse_issuer(&value.issuer)?,
+                kind: value.issuer_kind,
+                group: value.issuer_group,
+            },
+        })
+    }
+}
+
+// How the listeners of the Gateways of a class are made.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct GatewayClassPolicy {
+    // Of the listeners added, 443 by default.
+    pub https_port: Option<i32>,
+    // Of the listeners converted. Ports not listed take the HTTPS port.
+    #[serde(default)]
+    pub port_mapping: Vec<PortMapping>,
+    // Terminate by default, HTTPRoutes cannot attach to the others.
+    pub tls_mode: Option<GatewayListenersTlsMode>,
+    // Of the listeners added, the namespace of the Gateway by default.
+    pub allowed_routes: Option<GatewayListenersAllowedRoutes>,
+    // Merged into the listeners added or converted, such as `tls.options`.
+    pub listener: Option<serde_json::Value>,
+}
+impl GatewayClassPolicy {
+    // Traefik listens on unprivileged ports.
+    fn traefik() -> Self {
+        Self {
+            https_port: Some(8443),
+            port_mapping: vec![PortMapping {
+                http: 8000,
+                https: 8443,
+            }],
+            ..Default::default()
+        }
+    }
+
+    // The listener fields the mutations set are not to be overridden.
+    fn validate(&self) -> Result<()> {
+        if self
+            .tls_mode
+            .as_ref()
+            .is_some_and(|x| *x != GatewayListenersTlsMode::Terminate)
+        {
+            return Err(eyre!(
+                "tlsMode can only be Terminate, as HTTPRoutes attach to HTTPS listeners"
+            ));
+        }
+        if let Some(listener) = &self.listener {
+            let fields = listener
+                .as_object()
+                .ok_or_else(|| eyre!("listener should be a map of listener fields"))?;
+            if let Some(field) = ["name", "hostname", "port", "protocol"]
+                .iter()
+                .find(|x| fields.contains_key(**x))
+            {
+                return Err(eyre!("listener cannot set {field}"));
+            }
+            if listener
+                .pointer("/tls/mode")
+                .is_some_and(|x| x != "Terminate")
+            {
+                return Err(eyre!("listener can only set tls.mode to Terminate"));
+            }
+            self.apply_listener_fields(&GatewayListeners::default())?;
+        }
+        Ok(())
+    }
+
+    pub fn https_port(&self) -> i32 {
+        self.https_port.unwrap_or(443)
+    }
+
+    pub fn converted_port(&self, http_port: i32) -> i32 {
+        self.port_mapping
+            .iter()
+            .find(|x| x.http == http_port)
+            .map_or_else(|| self.https_port(), |x| x.https)
+    }
+
+    pub fn tls_mode(&self) -> GatewayListenersTlsMode {
+        self.tls_mode
+            .clone()
+            .unwrap_or(GatewayListenersTlsMode::Terminate)
+    }
+
+    pub fn allowed_routes(&self) -> GatewayListenersAllowedRoutes {
+        self.allowed_routes
+            .clone()
+            .unwrap_or(GatewayListenersAllowedRoutes {
+                kinds: None,
+                namespaces: Some(GatewayListenersAllowedRoutesNamespaces {
+                    from: Some(GatewayListenersAllowedRoutesNamespacesFrom::Same),
+                    selector: None,
+                }),
+            })
+    }
+
+    pub fn apply_listener_fields(&self, listener: &GatewayListeners) -> Result<GatewayListeners> {
+        let Some(fields) = &self.listener else {
+            return Ok(listener.clone());
+        };
+        let mut value = serde_json::to_value(listener)?;
+        json_patch::merge(&mut value, fields);
+        serde_json::from_value(value).map_err(|e| eyre!("Invalid listener fields: {e}"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortMapping {
+    pub http: i32,
+    pub https: i32,
+}
+
+// Names given to the objects the mutations make.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields, default)]
+pub struct Templates {
+    pub ingress_secret: NameTemplate,
+    pub gateway_listener: NameTemplate,
+    pub gateway_listener_secret: NameTemplate,
+    pub converted_listener_secret: NameTemplate,
+}
+impl Default for Templates {
+    fn default() -> Self {
+        Self {
+            ingress_secret: NameTemplate::new("{name}-tls"),
+            gateway_listener: NameTemplate::new("{gateway}-https"),
+            gateway_listener_secret: NameTemplate::new("{listener}-tls"),
+            converted_listener_secret: NameTemplate::new("{gateway}-{listener}-tls"),
+        }
+    }
+}
+impl Templates {
+    fn validate(&self) -> Result<()> {
+        const INGRESS: &[&str] = &["namespace", "name", "host", "hash"];
+        const LISTENER: &[&str] = &["namespace", "name", "gateway", "host", "hash"];
+        const LISTENER_SECRET: &[&str] =
+            &["namespace", "name", "gateway", "listener", "host", "hash"];
+        self.ingress_secret.validate(INGRESS)?;
+        self.gateway_listener.validate_label(LISTENER)?;
+        self.gateway_listener_secret.validate(LISTENER_SECRET)?;
+        self.converted_listener_secret.validate(LISTENER_SECRET)?;
+        Ok(())
+    }
+
+    pub fn ingress_secret(&self, variables: &NameVariables<'_>) -> String {
+        self.ingress_secret.render(variables, SUBDOMAIN_MAX)
+    }
+
+    pub fn gateway_listener(&self, variables: &NameVariables<'_>) -> String {
+        self.gateway_listener.render_label(variables)
+    }
+
+    pub fn gateway_listener_secret(&self, variables: &NameVariables<'_>) -> String {
+        self.gateway_listener_secret
+            .render(variables, SUBDOMAIN_MAX)
+    }
+
+    pub fn converted_listener_secret(&self, variables: &NameVariables<'_>) -> String {
+        self.converted_listener_secret
+            .render(variables, SUBDOMAIN_MAX)
+    }
+}
+
+// The policy file. Every setting is optional, those of command line flags
+// override the flags.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct PolicyFile {
+    issuer: Option<String>,
+    issuer_kind: Option<String>,
+    issuer_group: Option<String>,
+    traefik_redirect_middleware: Option<String>,
+    enforcement_mode: Option<EnforcementMode>,
+    deny_acme_http01_solvers: Option<bool>,
+    skip: Option<SkipPolicy>,
+    exempt_namespaces: Option<Vec<String>>,
+    checks: Option<BTreeMap<String, bool>>,
+    templates: Option<Templates>,
+    gateway_classes: Option<BTreeMap<String, GatewayClassPolicy>>,
+    gateway_controllers: Option<BTreeMap<String, GatewayClassPolicy>>,
+    issuer_rules: Option<Vec<IssuerRuleFile>>,
+    listener_certificates: Option<ListenerCertificates>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct SkipPolicy {
+    allowed_users: Option<Vec<String>>,
+    allowed_groups: Option<Vec<String>>,
+    subject_access_review: Option<bool>,
+    max_duration: Option<String>,
+}
+
+impl PolicyFile {
+    // YAML, or TOML by the extension.
+    fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| eyre!("Cannot read {}: {e}", path.display()))?;
+        let ret = if path.extension().is_some_and(|x| x == "toml") {
+            toml::from_str(&text).map_err(|e| eyre!("{e}"))
+        } else {
+            serde_yaml::from_str(&text).map_err(|e| eyre!("{e}"))
+        };
+        ret.map_err(|e| eyre!("Invalid policy file {}: {e}", path.display()))
+    }
+}
+
+impl Cli {
+    // The configuration with the policy file, if any, applied.
+    #[instrument(skip_all)]
+    pub fn with_policy_file(&self) -> Result<Self> {
+        let Some(path) = &self.policy_file else {
+            return Ok(self.clone());
+        };
+        self.with_policy(PolicyFile::load(path)?)
+    }
+
+    fn with_policy(&self, file: PolicyFile) -> Result<Self> {
+        let mut conf = self.clone();
+        if let Some(issuer) = file.issuer {
+            conf.cma = Some(CertManagerAnnotations {
+                issuer: parse_issuer(&issuer)?,
+                kind: None,
+                group: None,
+            });
+        }
+        if file.issuer_kind.is_some() || file.issuer_group.is_some() {
+            let cma = conf
+                .cma
+                .as_mut()
+                .ok_or_else(|| eyre!("issuerKind and issuerGroup need an issuer"))?;
+            cma.kind = file.issuer_kind.or_else(|| cma.kind.take());
+            cma.group = file.issuer_group.or_else(|| cma.group.take());
+        }
+        if let Some(x) = file.traefik_redirect_middleware {
+            conf.traefik_ingress_redirect_resource_name = Some(x);
+        }
+        if let Some(x) = file.enforcement_mode {
+            conf.enforcement_mode = x;
+        }
+        if let Some(x) = file.deny_acme_http01_solvers {
+            conf.deny_acme_http01_solvers = x;
+        }
+        if let Some(skip) = file.skip {
+            if let Some(x) = skip.allowed_users {
+                conf.skip_allowed_users = x;
+            }
+            if let Some(x) = skip.allowed_groups {
+                conf.skip_allowed_groups = x;
+            }
+            if let Some(x) = skip.subject_access_review {
+                conf.skip_subject_access_review = x;
+            }
+            if let Some(x) = skip.max_duration {
+                conf.skip_max_duration = Some(humantime::parse_duration(&x)?);
+            }
+        }
+        if let Some(x) = file.exempt_namespaces {
+            conf.policy.exempt_namespaces = x;
+        }
+        if let Some(x) = file.checks {
+            let toggleable = Rule::toggleable_names();
+            if let Some(unknown) = x.keys().find(|k| !toggleable.contains(&k.as_str())) {
+                return Err(eyre!(
+                    "Unknown check {unknown}, expecting one of {}",
+                    toggleable.join(", ")
+                ));
+            }
+            conf.policy.checks = x;
+        }
+        if let Some(x) = file.templates {
+            x.validate()?;
+            conf.policy.templates = x;
+        }
+        for (name, x) in file.gateway_classes.iter().flatten() {
+            x.validate()
+                .map_err(|e| eyre!("GatewayClass {name}: {e}"))?;
+        }
+        for (name, x) in file.gateway_controllers.iter().flatten() {
+            x.validate()
+                .map_err(|e| eyre!("Gateway controller {name}: {e}"))?;
+        }
+        if let Some(x) = file.gateway_classes {
+            conf.policy.gateway_classes.extend(x);
+        }
+        if let Some(x) = file.gateway_controllers {
+            conf.policy.gateway_controllers.extend(x);
+        }
+        if let Some(x) = file.issuer_rules {
+            conf.policy.issuer_rules = x
+                .into_iter()
+                .map(IssuerRule::try_from)
+                .collect::<Result<_>>()?;
+        }
+        if let Some(x) = file.listener_certificates {
+            conf.policy.listener_certificates = x;
+        }
+        Ok(conf)
+    }
+
+    // The issuer of a host, by the first matching rule or the default one.
+    fn issuer_of(&self, host: &str) -> Option<&CertManagerAnnotations> {
+        let host = host.trim_end_matches('.').to_lowercase();
+        self.policy
+            .issuer_rules
+            .iter()
+            .find(|x| x.matches(&host))
+            .map(|x| &x.issuer)
+            .or(self.cma.as_ref())
+    }
+
+    // The issuer to annotate the object with, checked to exist.
+    #[instrument(skip_all)]
+    pub async fn resolve_issuer(
+        &self,
+        metadata: &ObjectMeta,
+        hosts: &[String],
+        cluster: &Cluster,
+    ) -> std::result::Result<Option<CertManagerAnnotations>, DenyReason> {
+        // The annotation of the object wins over the one to add, whatever its
+        // namespace asks for.
+        if is_issuer_annotated(metadata) {
+            return self.select_issuer(metadata, hosts, None);
+        }
+        let namespace = metadata.namespace.as_deref().unwrap_or_default();
+        let namespace_issuer =
+            get_namespace_issuer(namespace, cluster).map_err(DenyReason::InvalidIssuer)?;
+        let issuer = self.select_issuer(metadata, hosts, namespace_issuer)?;
+        if let Some(issuer) = &issuer {
+            match cluster.issuer_exists(issuer, namespace).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Err(DenyReason::InvalidIssuer(format!(
+                        "{} of kind {} in group {} does not exist",
+                        issuer.issuer,
+                        issuer.kind.as_deref().unwrap_or("default"),
+                        issuer.group.as_deref().unwrap_or("cert-manager.io")
+                    )));
+                }
+                // Not knowing is no reason to deny, cert-manager reports
+                // the issuers it cannot find.
+                Err(e) => {
+                    tracing::warn!(target: "issuer-lookup", message = format!("{} is assumed to exist: {e}", issuer.issuer));
+                }
+            }
+        }
+        Ok(issuer)
+    }
+
+    // The issuer to annotate the object with. cert-manager takes the issuer
+    // of the object from its annotations, so the hosts have to agree on one.
+    // An issuer the object is already annotated with is kept, then the one of
+    // its namespace overrides the configured ones.
+    fn select_issuer(
+        &self,
+        metadata: &ObjectMeta,
+        hosts: &[String],
+        namespace_issuer: Option<CertManagerAnnotations>,
+    ) -> std::result::Result<Option<CertManagerAnnotations>, DenyReason> {
+        if is_issuer_annotated(metadata) {
+            return Ok(self.cma.clone());
+        }
+        if namespace_issuer.is_some() {
+            return Ok(namespace_issuer);
+        }
+        if self.policy.issuer_rules.is_empty() {
+            return Ok(self.cma.clone());
+        }
+        let mut issuers = vec![];
+        for issuer in hosts.iter().map(|host| self.issuer_of(host)) {
+            if !issuers.contains(&issuer) {
+                issuers.push(issuer);
+            }
+        }
+        if issuers.len() > 1 {
+            return Err(DenyReason::MixedIssuers(
+                hosts
+                    .iter()
+                    .map(|host| {
+                        (
+                            host.clone(),
+                            self.issuer_of(host)
+                                .map_or_else(|| "none".to_string(), |x| x.issuer.to_string()),
+                        )
+                    })
+                    .collect(),
+            ));
+        }
+        Ok(issuers
+            .first()
+            .map_or_else(|| self.cma.clone(), |x| x.cloned()))
+    }
+}
+
+fn is_issuer_annotated(metadata: &ObjectMeta) -> bool {
+    metadata
+        .annotations
+        .as_ref()
+        .is_some_and(|x| x.contains_key(ISSUER) || x.contains_key(CLUSTER_ISSUER))
+}
+
+// The configuration in effect, swapped as a whole when the policy file
+// changes.
+#[derive(Debug, Clone)]
+pub struct SharedConf {
+    inotify_thread: Option<Arc<Task<()>>>,
+    current: Arc<RwLock<Arc<Cli>>>,
+}
+impl SharedConf {
+    // Without the policy file, or not watching it.
+    pub fn fixed(conf: Cli) -> Self {
+        Self {
+            inotify_thread: None,
+            current: Arc::new(RwLock::new(Arc::new(conf))),
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub fn watch(base: Cli) -> Result<Self> {
+        let mut self_ = Self::fixed(base.with_policy_file()?);
+        if let Some(path) = base.policy_file.clone() {
+            let the_field = self_.current.clone();
+            self_.inotify_thread = Some(Arc::new(unblock(move || {
+                if let Err(e) = Self::watch_file(&the_field, &base, &path) {
+                    tracing::error!(target: "policy-hot-reload", message = format!("{e:?}"));
+                }
+            })));
+        }
+        Ok(self_)
+    }
+
+    pub async fn current(&self) -> Arc<Cli> {
+        self.current.read().await.clone()
+    }
+
+    // Files mounted from a ConfigMap are swapped through the `..data`
+    // symlink, others are written in place.
+    #[instrument(skip_all)]
+    fn watch_file(the_field: &Arc<RwLock<Arc<Cli>>>, base: &Cli, path: &Path) -> Result<()> {
+        let folder = path
+            .parent()
+            .filter(|x| !x.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let file_name = path.file_name().unwrap_or_default().to_owned();
+        let mut inotify = Inotify::init()?;
+        inotify.watches().add(
+            folder,
+            WatchMask::DELETE | WatchMask::CREATE | WatchMask::MOVED_TO | WatchMask::CLOSE_WRITE,
+        )?;
+
+        let mut buffer = [0; 4096];
+        loop {
+            let events = inotify.read_events_blocking(&mut buffer)?;
+            if !events
+                .into_iter()
+                .any(|event| event.name.is_some_and(|x| x == "..data" || x == file_name))
+            {
+                continue;
+            }
+            match base.with_policy_file() {
+                Ok(conf) => {
+                    *the_field.write_arc_blocking() = Arc::new(conf);
+                    metrics::observe_policy_reload(true);
+                    tracing::info!(target: "policy-hot-reload", message = "Policy reloaded");
+                }
+                Err(e) => {
+                    metrics::observe_policy_reload(false);
+                    tracing::error!(target: "policy-hot-reload", message = format!("Keeping the previous policy. {e:?}"));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use gateway_api::gateways::GatewayListenersTls;
+    use k8s_openapi::api::networking::v1::Ingress;
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::{
+        cluster::{Cluster, ClusterObjects},
+        helpers::{
+            DenyReason, ISSUER_GROUP, ISSUER_KIND, Issuer, NAMESPACE_ISSUER_ANNOTATION,
+            NAMESPACE_ISSUER_GROUP_ANNOTATION, NAMESPACE_ISSUER_KIND_ANNOTATION, Requester, Status,
+        },
+        ingress::{mutate_ingress, validate_ingress},
+        testing::{apply, call_in, cli, ingress, review},
+    };
+
+    fn conf() -> Cli {
+        cli(&[])
+    }
+
+    #[test]
+    fn file_overrides_flags() {
+        let file: PolicyFile = serde_yaml::from_str(
+            r#"
+issuer: namespaced:step-ca
+enforcementMode: warn
+skip:
+  allowedGroups: [platform]
+  maxDuration: 7d
+exemptNamespaces: [kube-system]
+checks:
+  IngressHostsNotCoveredByTLS: false
+templates:
+  ingressSecret: "tls-{name}"
+gatewayClasses:
+  envoy:
+    httpsPort: 10443
+"#,
+        )
+        .unwrap();
+        let conf = conf().with_policy(file).unwrap();
+        assert!(matches!(
+            conf.cma.as_ref().map(|x| &x.issuer),
+            Some(Issuer::Namespaced(x)) if x == "step-ca"
+        ));
+        assert_eq!(conf.enforcement_mode, EnforcementMode::Warn);
+        assert_eq!(conf.skip_allowed_groups, ["platform"]);
+        assert!(conf.policy.is_exempt("kube-system"));
+        assert!(!conf.policy.is_check_enabled("IngressHostsNotCoveredByTLS"));
+        assert!(conf.policy.is_check_enabled("IngressNoTLS"));
+        assert_eq!(
+            conf.policy.templates.ingress_secret(&NameVariables {
+                name: "whoami",
+                ..Default::default()
+            }),
+            "tls-whoami"
+        );
+        assert_eq!(conf.policy.gateway_class("envoy", None).https_port(), 10443);
+        assert_eq!(
+            conf.policy.gateway_class("traefik", None).https_port(),
+            8443
+        );
+        assert_eq!(conf.policy.gateway_class("istio", None).https_port(), 443);
+    }
+
+    #[test]
+    fn gateway_classes_by_name_or_controller() {
+        let file: PolicyFile = serde_yaml::from_str(
+            r"
+gatewayClasses:
+  internal:
+    httpsPort: 9443
+gatewayControllers:
+  gateway.envoyproxy.io/gatewayclass-controller:
+    httpsPort: 10443
+    portMapping:
+    - {http: 10080, https: 10443}
+    - {http: 80, https: 443}
+    tlsMode: Terminate
+    allowedRoutes:
+      namespaces:
+        from: All
+    listener:
+      tls:
+        mode: Terminate
+        options:
+          gateway.envoyproxy.io/tls-min-version: '1.3'
+",
+        )
+        .unwrap();
+        let conf = conf().with_policy(file).unwrap();
+        let controller = Some("gateway.envoyproxy.io/gatewayclass-controller");
+        // The name wins over the controller.
+        assert_eq!(
+            conf.policy
+                .gateway_class("internal", controller)
+                .https_port(),
+            9443
+        );
+        let class = conf.policy.gateway_class("eg", controller);
+        assert_eq!(class.https_port(), 10443);
+        assert_eq!(class.converted_port(80), 443);
+        assert_eq!(class.converted_port(8080), 10443);
+        assert_eq!(
+            class.allowed_routes().namespaces.and_then(|x| x.from),
+            Some(GatewayListenersAllowedRoutesNamespacesFrom::All)
+        );
+        let listener = class
+            .apply_listener_fields(&GatewayListeners {
+                name: "https".to_string(),
+                tls: Some(GatewayListenersTls {
+                    mode: Some(class.tls_mode()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(listener.name, "https");
+        let tls = listener.tls.unwrap();
+        assert_eq!(tls.mode, Some(GatewayListenersTlsMode::Terminate));
+        assert_eq!(
+            tls.options.unwrap()["gateway.envoyproxy.io/tls-min-version"],
+            "1.3"
+        );
+        assert_eq!(
+            conf.policy
+                .gateway_class("traefik-internal", Some("traefik.io/gateway-controller"))
+                .converted_port(8000),
+            8443
+        );
+    }
+
+    #[test]
+    fn invalid_policy_is_rejected() {
+        for text in [
+            "checks: {NoSuchCheck: false}",
+            "templates: {ingressSecret: \"{gateway}-tls\"}",
+            "templates: {gatewayListener: \"{gateway}_https\"}",
+            "issuer: letsencrypt",
+            "unknownSetting: true",
+            "gatewayClasses: {envoy: {tlsMode: Passthrough}}",
+            "gatewayClasses: {envoy: {listener: {tls: {mode: Passthrough}}}}",
+            "gatewayClasses: {envoy: {listener: {port: 8443}}}",
+            "gatewayControllers: {envoy: {listener: {tls: {mode: Reencrypt}}}}",
+        ] {
+            let file = serde_yaml::from_str::<PolicyFile>(text);
+            assert!(
+                file.map_or(true, |f| conf().with_policy(f).is_err()),
+                "{text}"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn checks_follow_the_policy() {
+        let ingress: Ingress = serde_yaml::from_str(
+            r"
+metadata:
+  name: whoami
+  namespace: test
+spec:
+  rules:
+  - host: whoami.magicloud.lan
+",
+        )
+        .unwrap();
+        let ingress = Arc::new(ingress);
+        let cluster = Cluster::from_objects(ClusterObjects::default());
+        let requester = Requester {
+            offline: true,
+            ..Default::default()
+        };
+        let run = |conf: Cli| {
+            let ingress = ingress.clone();
+            let cluster = cluster.clone();
+            let requester = requester.clone();
+            async move {
+                validate_ingress(&conf, &cluster, &requester)
+                    .run(ingress)
+                    .await
+            }
+        };
+        let policy = |text: &str| {
+            conf()
+                .with_policy(serde_yaml::from_str(text).unwrap())
+                .unwrap()
+        };
+
+        assert!(matches!(run(conf()).await, Some(Ok(Status::Denied(_)))));
+        assert!(matches!(
+            run(policy(
+                "checks: {IngressNoTLS: false, IngressHostsNotCoveredByTLS: false}"
+            ))
+            .await,
+            Some(Ok(Status::MoveOn))
+        ));
+        assert!(matches!(
+            run(policy("exemptNamespaces: [test]")).await,
+            Some(Ok(Status::Allowed))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn issuers_follow_hosts() {
+        let ingress: Ingress = serde_yaml::from_str(
+            r"
+metadata:
+  name: whoami
+  namespace: test
+spec:
+  rules:
+  - host: whoami.magicloud.lan
+  - host: whoami.example.com
+",
+        )
+        .unwrap();
+        let ingress = Arc::new(ingress);
+        let cluster = Cluster::from_objects(ClusterObjects::default());
+        let requester = Requester {
+            offline: true,
+            ..Default::default()
+        };
+        let rules = r"
+issuerRules:
+- hostSuffix: .lan
+  issuer: namespaced:step-ca
+  issuerKind: StepClusterIssuer
+  issuerGroup: certmanager.step.sm
+- hostRegex: '\.example\.com$'
+  issuer: clustered:letsencrypt-prod
+";
+        let policy = |text: &str| {
+            conf()
+                .with_policy(serde_yaml::from_str(text).unwrap())
+                .unwrap()
+        };
+
+        let conf = policy(rules);
+        let lan = ["a.magicloud.lan".to_string()];
+        assert_eq!(
+            conf.select_issuer(&ObjectMeta::default(), &lan, None)
+                .unwrap()
+                .as_ref()
+                .map(|x| x.issuer.to_string()),
+            Some("namespaced:step-ca".to_string())
+        );
+        let Some(Ok(Status::Denied(DenyReason::MixedIssuers(hosts)))) =
+            mutate_ingress(ingress, &conf, &cluster, &requester).await
+        else {
+            panic!("Mixed issuers are not denied");
+        };
+        assert_eq!(
+            hosts,
+            [
+                (
+                    "whoami.magicloud.lan".to_string(),
+                    "namespaced:step-ca".to_string()
+                ),
+                (
+                    "whoami.example.com".to_string(),
+                    "clustered:letsencrypt-prod".to_string()
+                )
+            ]
+        );
+        assert!(
+            conf.with_policy(serde_yaml::from_str("issuerRules: [{issuer: clustered:x}]").unwrap())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn toml_policy() {
+        let mut file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        file.write_all(b"enforcementMode = \"audit\"\nexemptNamespaces = [\"a\"]\n")
+            .unwrap();
+        let mut base = conf();
+        base.policy_file = Some(file.path().to_path_buf());
+        let conf = base.with_policy_file().unwrap();
+        assert_eq!(conf.enforcement_mode, EnforcementMode::Audit);
+        assert!(conf.policy.is_exempt("a"));
+    }
+
+    #[actix_web::test]
+    async fn namespace_annotation_overrides_issuer() {
+        let object = ingress("nginx", None);
+        let cluster = |annotations: Value| {
+            let namespace = serde_json::from_value(json!({
+                "apiVersion": "v1",
+                "kind": "Namespace",
+                "metadata": {"name": "test", "annotations": annotations}
+            }))
+            .unwrap();
+            Cluster::from_objects(ClusterObjects {
+                namespaces: vec![namespace],
+                ..Default::default()
+            })
+        };
+
+        let ret = call_in(
+            cluster(json!({
+                NAMESPACE_ISSUER_ANNOTATION: "namespaced:team-a-ca",
+                NAMESPACE_ISSUER_KIND_ANNOTATION: "StepIssuer",
+                NAMESPACE_ISSUER_GROUP_ANNOTATION: "certmanager.step.sm"
+            })),
+            "/mutate",
+            &review(&object),
+            &[],
+        )
+        .await;
+        let annotations = &apply(&object, &ret["response"])["metadata"]["annotations"];
+        assert_eq!(annotations[ISSUER], "team-a-ca");
+        assert_eq!(annotations[ISSUER_KIND], "StepIssuer");
+        assert_eq!(annotations[ISSUER_GROUP], "certmanager.step.sm");
+        assert!(annotations.get(CLUSTER_ISSUER).is_none());
+
+        let ret = call_in(
+            cluster(json!({NAMESPACE_ISSUER_KIND_ANNOTATION: "StepIssuer"})),
+            "/mutate",
+            &review(&object),
+            &[],
+        )
+        .await;
+        assert_eq!(ret["response"]["allowed"], false);
+        let message = ret["response"]["status"]["message"].as_str().unwrap();
+        assert!(message.contains("The issuer is not valid"));
+
+        // The issuer the object is annotated with wins over its namespace.
+        let mut object = object;
+        object["metadata"]["annotations"] = json!({CLUSTER_ISSUER: "letsencrypt-staging"});
+        let ret = call_in(
+            cluster(json!({
+                NAMESPACE_ISSUER_ANNOTATION: "namespaced:team-a-ca",
+                NAMESPACE_ISSUER_KIND_ANNOTATION: "StepIssuer"
+            })),
+            "/mutate",
+            &review(&object),
+            &[],
+        )
+        .await;
+        let annotations = &apply(&object, &ret["response"])["metadata"]["annotations"];
+        assert_eq!(annotations[CLUSTER_ISSUER], "letsencrypt-staging");
+        assert!(annotations.get(ISSUER).is_none());
+        assert!(annotations.get(ISSUER_KIND).is_none());
+
+        let ret = call_in(
+            cluster(json!({NAMESPACE_ISSUER_KIND_ANNOTATION: "StepIssuer"})),
+            "/mutate",
+            &review(&object),
+            &[],
+        )
+        .await;
+        assert_eq!(ret["response"]["allowed"], true);
+    }
+}
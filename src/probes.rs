@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use actix_web::{HttpResponse, Responder, get, http::header::ContentType, web::Data};
+use prometheus::TEXT_FORMAT;
+
+use crate::{cluster::Cluster, metrics, tls_cert_resolver::TLSCertResolver};
+
+#[get("/healthz")]
+async fn get_healthz() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body("ok")
+}
+
+#[get("/readyz")]
+async fn get_readyz(
+    cert_resolver: Data<Arc<TLSCertResolver>>,
+    cluster: Data<Cluster>,
+) -> impl Responder {
+    let mut problems = vec![];
+    if !cert_resolver.is_certificate_valid().await {
+        problems.push("TLS certificate is expired or not yet valid");
+    }
+    if !cluster.is_synced() {
+        problems.push("Cluster cache is not synced");
+    }
+    if problems.is_empty() {
+        HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .body("ok")
+    } else {
+        HttpResponse::ServiceUnavailable()
+            .content_type(ContentType::plaintext())
+            .body(problems.join("\n"))
+    }
+}
+
+#[get("/metrics")]
+async fn get_metrics() -> impl Responder {
+    match metrics::gather() {
+        Ok(body) => HttpResponse::Ok().content_type(TEXT_FORMAT).body(body),
+        Err(e) => {
+            tracing::warn!(target: "metrics", message = format!("{e:?}"));
+            HttpResponse::InternalServerError().body(e.to_string())
+        }
+    }
+}
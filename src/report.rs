@@ -0,0 +1,576 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use eyre::Result;
+use itertools::Itertools;
+use serde_json::{Value, json};
+
+use crate::{
+    cli::ReportFormat,
+    helpers::{DenyReason, Rule},
+};
+
+// Listeners or routes involved in a violation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Related {
+    pub kind: String,
+    pub namespace: String,
+    pub name: String,
+    // Listener name.
+    pub section: Option<String>,
+}
+impl Display for Related {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}/{}", self.kind, self.namespace, self.name)?;
+        if let Some(section) = &self.section {
+            write!(f, " listener {section}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub rule: String,
+    pub message: String,
+    pub related: Vec<Related>,
+}
+impl From<&DenyReason> for Violation {
+    fn from(reason: &DenyReason) -> Self {
+        Self {
+            rule: reason.name().to_string(),
+            message: reason.to_string(),
+            related: related_objects(reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Denied(Violation),
+    Warned(Violation),
+    Error(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+    // File name, None for stdin or the cluster.
+    pub source: Option<String>,
+    pub line: Option<usize>,
+    pub api_version: String,
+    pub kind: String,
+    pub namespace: String,
+    pub name: String,
+    pub outcome: Outcome,
+}
+impl Finding {
+    pub const fn is_failure(&self) -> bool {
+        matches!(self.outcome, Outcome::Denied(_) | Outcome::Error(_))
+    }
+
+    const fn level(&self) -> Option<&'static str> {
+        match self.outcome {
+            Outcome::Passed => None,
+            Outcome::Denied(_) | Outcome::Error(_) => Some("error"),
+            Outcome::Warned(_) => Some("warning"),
+        }
+    }
+
+    fn rule(&self) -> &str {
+        match &self.outcome {
+            Outcome::Passed => "",
+            Outcome::Denied(v) | Outcome::Warned(v) => &v.rule,
+            Outcome::Error(_) => "InternalError",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match &self.outcome {
+            Outcome::Passed => "",
+            Outcome::Denied(v) | Outcome::Warned(v) => &v.message,
+            Outcome::Error(e) => e,
+        }
+    }
+
+    fn related(&self) -> &[Related] {
+        match &self.outcome {
+            Outcome::Denied(v) | Outcome::Warned(v) => &v.related,
+            Outcome::Passed | Outcome::Error(_) => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub checked: usize,
+    pub failed: usize,
+    pub warned: usize,
+}
+impl Counts {
+    fn add(&mut self, finding: &Finding) {
+        self.checked += 1;
+        self.failed += usize::from(finding.is_failure());
+        self.warned += usize::from(matches!(finding.outcome, Outcome::Warned(_)));
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub total: Counts,
+    pub namespaces: BTreeMap<String, Counts>,
+    // Findings by rule, warnings included.
+    pub reasons: BTreeMap<String, usize>,
+}
+impl Summary {
+    pub fn of(findings: &[Finding]) -> Self {
+        let mut summary = Self::default();
+        for f in findings {
+            summary.total.add(f);
+            summary
+                .namespaces
+                .entry(f.namespace.clone())
+                .or_default()
+                .add(f);
+            if f.level().is_some() {
+                *summary.reasons.entry(f.rule().to_string()).or_default() += 1;
+            }
+        }
+        summary
+    }
+
+    fn to_json(&self) -> Value {
+        let counts =
+            |c: &Counts| json!({"checked": c.checked, "failed": c.failed, "warned": c.warned});
+        let mut value = counts(&self.total);
+        value["namespaces"] = self
+            .namespaces
+            .iter()
+            .map(|(ns, c)| (ns.clone(), counts(c)))
+            .collect::<serde_json::Map<_, _>>()
+            .into();
+        value["reasons"] = json!(self.reasons);
+        value
+    }
+}
+
+fn related_objects(reason: &DenyReason) -> Vec<Related> {
+    match reason {
+        DenyReason::GatewayNonRedirectHTTPRouteAttachedToHTTPListener(listener_routes) => {
+            listener_routes
+                .iter()
+                .flat_map(|(listener, routes)| {
+                    routes.bad.iter().map(|route| Related {
+                        kind: "HTTPRoute".to_string(),
+                        namespace: route.metadata.namespace.clone().unwrap_or_default(),
+                        name: route.metadata.name.clone().unwrap_or_default(),
+                        section: Some(listener.name.clone()),
+                    })
+                })
+                .collect()
+        }
+        DenyReason::HTTPRouteNonRedirectAttachedToHTTPListener(gateway_listeners) => {
+            gateway_listeners
+                .iter()
+                .flat_map(|(_, glp)| {
+                    let gateway = glp.borrow_gateway();
+                    glp.borrow_listeners()
+                        .iter()
+                        .map(|listener| Related {
+                            kind: "Gateway".to_string(),
+                            namespace: gateway.metadata.namespace.clone().unwrap_or_default(),
+                            name: gateway.metadata.name.clone().unwrap_or_default(),
+                            section: Some(listener.name.clone()),
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        }
+        _ => vec![],
+    }
+}
+
+pub fn render(format: ReportFormat, findings: &[Finding]) -> Result<String> {
+    Ok(match format {
+        ReportFormat::Text => render_text(findings),
+        ReportFormat::Json => serde_json::to_string_pretty(&render_json(findings))?,
+        ReportFormat::Sarif => serde_json::to_string_pretty(&render_sarif(findings))?,
+        ReportFormat::Junit => render_junit(findings),
+        ReportFormat::Github => render_github(findings),
+    })
+}
+
+fn render_text(findings: &[Finding]) -> String {
+    let mut lines = findings
+        .iter()
+        .filter_map(|f| {
+            let location = match (&f.source, f.line) {
+                (Some(source), Some(line)) => format!(" in {source}:{line}"),
+                (Some(source), None) => format!(" in {source}"),
+                (None, _) => String::new(),
+            };
+            let text = format!(
+                "{}: {} {}/{}{location}: {}",
+                f.level()?,
+                f.kind,
+                f.namespace,
+                f.name,
+                f.message()
+            );
+            Some(
+                std::iter::once(text)
+                    .chain(f.related().iter().map(|r| format!("  - {r}")))
+                    .join("\n"),
+            )
+        })
+        .collect::<Vec<_>>();
+    let summary = Summary::of(findings);
+    for (ns, c) in &summary.namespaces {
+        lines.push(format!(
+            "namespace {ns}: {} checked, {} failed, {} warned",
+            c.checked, c.failed, c.warned
+        ));
+    }
+    for (rule, count) in &summary.reasons {
+        lines.push(format!("reason {rule}: {count}"));
+    }
+    lines.push(format!(
+        "{} objects checked, {} failed",
+        summary.total.checked, summary.total.failed
+    ));
+    lines.join("\n")
+}
+
+fn render_json(findings: &[Finding]) -> Value {
+    json!({
+        "version": 1,
+        "summary": Summary::of(findings).to_json(),
+        "findings": findings.iter().filter(|f| f.level().is_some()).map(|f| json!({
+            "rule": f.rule(),
+            "level": f.level(),
+            "message": f.message(),
+            "resource": {
+                "apiVersion": f.api_version,
+                "kind": f.kind,
+                "namespace": f.namespace,
+                "name": f.name,
+            },
+            "location": {"file": f.source, "line": f.line},
+            "related": f.related().iter().map(|r| json!({
+                "kind": r.kind,
+                "namespace": r.namespace,
+                "name": r.name,
+                "listener": r.section,
+            })).collect::<Vec<_>>(),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+fn render_sarif(findings: &[Finding]) -> Value {
+    let failed = findings.iter().filter(|f| f.level().is_some());
+    let rules = failed
+        .clone()
+        .map(Finding::rule)
+        .unique()
+        .sorted()
+        .map(|rule| {
+            json!({
+                "id": rule,
+                "shortDescription": {"text": Rule::description_of(rule).unwrap_or_default()},
+            })
+        })
+        .collect::<Vec<_>>();
+    let results = failed
+        .map(|f| {
+            let mut location = json!({
+                "logicalLocations": [{
+                    "fullyQualifiedName": format!("{}/{}/{}/{}", f.api_version, f.kind, f.namespace, f.name),
+                    "kind": "resource",
+                }],
+            });
+            if let Some(file) = &f.source {
+                location["physicalLocation"] = json!({"artifactLocation": {"uri": file}});
+                if let Some(line) = f.line {
+                    location["physicalLocation"]["region"] = json!({"startLine": line});
+                }
+            }
+            json!({
+                "ruleId": f.rule(),
+                "level": f.level(),
+                "message": {"text": f.message()},
+                "locations": [location],
+                "relatedLocations": f.related().iter().enumerate().map(|(i, r)| json!({
+                    "id": i,
+                    "message": {"text": r.to_string()},
+                    "logicalLocations": [{
+                        "fullyQualifiedName": format!("{}/{}/{}", r.kind, r.namespace, r.name),
+                        "kind": "resource",
+                    }],
+                })).collect::<Vec<_>>(),
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {"driver": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+                "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                "rules": rules,
+            }},
+            "results": results,
+            "properties": {"summary": Summary::of(findings).to_json()},
+        }],
+    })
+}
+
+// One test suite per namespace, with the findings by rule as properties.
+fn render_junit(findings: &[Finding]) -> String {
+    let summary = Summary::of(findings);
+    let testsuites = summary
+        .namespaces
+        .iter()
+        .map(|(ns, counts)| {
+            let findings = findings.iter().filter(|f| &f.namespace == ns).collect::<Vec<_>>();
+            let errors = findings
+                .iter()
+                .filter(|f| matches!(f.outcome, Outcome::Error(_)))
+                .count();
+            let properties = findings
+                .iter()
+                .filter(|f| f.level().is_some())
+                .counts_by(|f| f.rule())
+                .into_iter()
+                .sorted()
+                .map(|(rule, count)| {
+                    format!("<property name=\"reason.{}\" value=\"{count}\"/>", xml_escape(rule))
+                })
+                .join("");
+            format!(
+                "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{errors}\">\n<properties>{properties}</properties>\n{}</testsuite>\n",
+                xml_escape(ns),
+                counts.checked,
+                counts.failed - errors,
+                findings.iter().map(|f| junit_testcase(f)).collect::<String>()
+            )
+        })
+        .join("");
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites name=\"{}\" tests=\"{}\" failures=\"{}\">\n{testsuites}</testsuites>",
+        env!("CARGO_PKG_NAME"),
+        summary.total.checked,
+        summary.total.failed
+    )
+}
+
+fn junit_testcase(f: &Finding) -> String {
+    let body = match &f.outcome {
+        Outcome::Passed => String::new(),
+        Outcome::Denied(v) => format!(
+            "<failure type=\"{}\" message=\"{}\">{}</failure>",
+            xml_escape(&v.rule),
+            xml_escape(&v.message),
+            xml_escape(&f.related().iter().join("\n"))
+        ),
+        Outcome::Warned(v) => format!(
+            "<system-out>warning: {}</system-out>",
+            xml_escape(&v.message)
+        ),
+        Outcome::Error(e) => format!("<error message=\"{}\"/>", xml_escape(e)),
+    };
+    let file = f
+        .source
+        .as_ref()
+        .map(|source| format!(" file=\"{}\"", xml_escape(source)))
+        .unwrap_or_default();
+    format!(
+        "<testcase classname=\"{}\" name=\"{}\"{file}>{body}</testcase>\n",
+        xml_escape(&format!("{}/{}", f.api_version, f.kind)),
+        xml_escape(&format!("{}/{}", f.namespace, f.name)),
+    )
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// https://docs.github.com/en/actions/reference/workflow-commands-for-github-actions
+fn render_github(findings: &[Finding]) -> String {
+    let summary = Summary::of(findings);
+    let notice = std::iter::once(format!(
+        "{} objects checked, {} failed, {} warned",
+        summary.total.checked, summary.total.failed, summary.total.warned
+    ))
+    .chain(summary.namespaces.iter().map(|(ns, c)| {
+        format!(
+            "namespace {ns}: {} checked, {} failed, {} warned",
+            c.checked, c.failed, c.warned
+        )
+    }))
+    .chain(
+        summary
+            .reasons
+            .iter()
+            .map(|(rule, count)| format!("reason {rule}: {count}")),
+    )
+    .join("\n");
+    findings
+        .iter()
+        .filter_map(|f| {
+            let mut properties = vec![];
+            if let Some(file) = &f.source {
+                properties.push(format!("file={}", github_escape_property(file)));
+            }
+            if let Some(line) = f.line {
+                properties.push(format!("line={line}"));
+            }
+            properties.push(format!(
+                "title={}",
+                github_escape_property(&format!(
+                    "{} {}/{}: {}",
+                    f.kind,
+                    f.namespace,
+                    f.name,
+                    f.rule()
+                ))
+            ));
+            let message = std::iter::once(f.message().to_string())
+                .chain(f.related().iter().map(|r| format!("- {r}")))
+                .join("\n");
+            Some(format!(
+                "::{} {}::{}",
+                f.level()?,
+                properties.join(","),
+                github_escape_data(&message)
+            ))
+        })
+        .chain(std::iter::once(format!(
+            "::notice title={}::{}",
+            env!("CARGO_PKG_NAME"),
+            github_escape_data(&notice)
+        )))
+        .join("\n")
+}
+
+fn github_escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn github_escape_property(s: &str) -> String {
+    github_escape_data(s)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn findings() -> Vec<Finding> {
+        let finding = |name: &str, outcome| Finding {
+            source: Some("deploy/app.yaml".to_string()),
+            line: Some(3),
+            api_version: "gateway.networking.k8s.io/v1".to_string(),
+            kind: "Gateway".to_string(),
+            namespace: "test".to_string(),
+            name: name.to_string(),
+            outcome,
+        };
+        vec![
+            finding("good", Outcome::Passed),
+            finding(
+                "bad",
+                Outcome::Denied(Violation {
+                    rule: "GatewayNonRedirectHTTPRouteAttachedToHTTPListener".to_string(),
+                    message: "Routes <a> & \"b\", 100%\nattached".to_string(),
+                    related: vec![Related {
+                        kind: "HTTPRoute".to_string(),
+                        namespace: "test".to_string(),
+                        name: "a".to_string(),
+                        section: Some("http".to_string()),
+                    }],
+                }),
+            ),
+        ]
+    }
+
+    #[test]
+    fn machine_readable_formats() {
+        let findings = findings();
+
+        let report: Value =
+            serde_json::from_str(&render(ReportFormat::Json, &findings).unwrap()).unwrap();
+        assert_eq!(report["summary"]["failed"], 1);
+        assert_eq!(report["findings"][0]["location"]["line"], 3);
+        assert_eq!(report["findings"][0]["related"][0]["listener"], "http");
+
+        let sarif: Value =
+            serde_json::from_str(&render(ReportFormat::Sarif, &findings).unwrap()).unwrap();
+        let result = &sarif["runs"][0]["results"][0];
+        assert_eq!(
+            result["ruleId"],
+            "GatewayNonRedirectHTTPRouteAttachedToHTTPListener"
+        );
+        assert_eq!(
+            result["locations"][0]["physicalLocation"]["region"]["startLine"],
+            3
+        );
+        assert_eq!(
+            result["relatedLocations"][0]["message"]["text"],
+            "HTTPRoute test/a listener http"
+        );
+
+        let junit = render(ReportFormat::Junit, &findings).unwrap();
+        assert!(junit.contains(r#"<testsuite name="test" tests="2" failures="1" errors="0">"#));
+        assert!(junit.contains(
+            r#"name="reason.GatewayNonRedirectHTTPRouteAttachedToHTTPListener" value="1""#
+        ));
+        assert!(junit.contains("Routes &lt;a&gt; &amp; &quot;b&quot;"));
+
+        let github = render(ReportFormat::Github, &findings).unwrap();
+        assert_eq!(
+            github.lines().next().unwrap(),
+            "::error file=deploy/app.yaml,line=3,title=Gateway test/bad%3A GatewayNonRedirectHTTPRouteAttachedToHTTPListener::Routes <a> & \"b\", 100%25%0Aattached%0A- HTTPRoute test/a listener http"
+        );
+    }
+
+    #[test]
+    fn summary_by_namespace_and_reason() {
+        let mut findings = findings();
+        let mut warned = findings[1].clone();
+        warned.namespace = "other".to_string();
+        warned.outcome = match warned.outcome {
+            Outcome::Denied(v) => Outcome::Warned(v),
+            outcome => outcome,
+        };
+        findings.push(warned);
+        let summary = Summary::of(&findings);
+        assert_eq!(
+            summary.total,
+            Counts {
+                checked: 3,
+                failed: 1,
+                warned: 1
+            }
+        );
+        assert_eq!(
+            summary.namespaces.keys().collect::<Vec<_>>(),
+            ["other", "test"]
+        );
+        assert_eq!(summary.namespaces["other"].warned, 1);
+        assert_eq!(
+            summary.reasons["GatewayNonRedirectHTTPRouteAttachedToHTTPListener"],
+            2
+        );
+        let report: Value =
+            serde_json::from_str(&render(ReportFormat::Json, &findings).unwrap()).unwrap();
+        assert_eq!(report["summary"]["namespaces"]["test"]["failed"], 1);
+    }
+}
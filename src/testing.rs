@@ -0,0 +1,169 @@
+// Fixtures shared by the tests of the modules. Objects go through the webhook
+// endpoints, as the API server sends them.
+use std::io::Write;
+
+use actix_web::{App, test, web::Data};
+use clap::Parser;
+use serde_json::{Value, json};
+use tempfile::NamedTempFile;
+
+use crate::{
+    cli::Cli,
+    cluster::{Cluster, ClusterObjects},
+    policy::SharedConf,
+    webhook::{post_mutate, post_validate},
+};
+
+pub fn cli(extra: &[&str]) -> Cli {
+    let args = [
+        "ingress-tls",
+        "-f",
+        "/tls",
+        "-c",
+        "tls.crt",
+        "-k",
+        "tls.key",
+        "--issuer",
+        "clustered:letsencrypt",
+        "-t",
+        "test/https-redirect",
+    ];
+    Cli::parse_from(args.iter().chain(extra))
+        .with_policy_file()
+        .unwrap()
+}
+
+// Removed when dropped, named apart for tests running in parallel.
+pub fn policy_file(content: &str) -> NamedTempFile {
+    let mut file = tempfile::Builder::new()
+        .prefix("ingress-tls-policy-")
+        .suffix(".yaml")
+        .tempfile()
+        .unwrap();
+    file.write_all(content.as_bytes()).unwrap();
+    file
+}
+
+pub fn review(object: &Value) -> Value {
+    let api_version = object["apiVersion"].as_str().unwrap_or_default();
+    let (group, version) = api_version.rsplit_once('/').unwrap_or(("", api_version));
+    let kind = object["kind"].as_str().unwrap_or_default();
+    let resource = format!("{}s", kind.to_lowercase());
+    json!({
+        "apiVersion": "admission.k8s.io/v1",
+        "kind": "AdmissionReview",
+        "request": {
+            "uid": "705ab4f5-6393-11e8-b7cc-42010a800002",
+            "kind": {"group": group, "version": version, "kind": kind},
+            "resource": {"group": group, "version": version, "resource": resource},
+            "requestKind": {"group": group, "version": version, "kind": kind},
+            "requestResource": {"group": group, "version": version, "resource": resource},
+            "name": object["metadata"]["name"],
+            "namespace": object["metadata"]["namespace"],
+            "operation": "CREATE",
+            "userInfo": {"username": "admin", "groups": ["system:authenticated"]},
+            "object": object,
+            "oldObject": null,
+            "dryRun": false,
+            "options": {"apiVersion": "meta.k8s.io/v1", "kind": "CreateOptions"}
+        }
+    })
+}
+
+pub fn ingress(class: &str, tls: Option<Value>) -> Value {
+    let mut spec = json!({
+        "ingressClassName": class,
+        "rules": [{
+            "host": "whoami.magicloud.lan",
+            "http": {"paths": [{
+                "path": "/",
+                "pathType": "Prefix",
+                "backend": {"service": {"name": "whoami", "port": {"number": 80}}}
+            }]}
+        }]
+    });
+    if let Some(tls) = tls {
+        spec["tls"] = tls;
+    }
+    json!({
+        "apiVersion": "networking.k8s.io/v1",
+        "kind": "Ingress",
+        "metadata": {"name": "whoami", "namespace": "test"},
+        "spec": spec
+    })
+}
+
+pub fn gateway() -> Value {
+    json!({
+        "apiVersion": "gateway.networking.k8s.io/v1",
+        "kind": "Gateway",
+        "metadata": {"name": "whoami", "namespace": "test"},
+        "spec": {
+            "gatewayClassName": "traefik",
+            "listeners": [
+                {"name": "http", "port": 8000, "protocol": "HTTP"},
+                {
+                    "name": "https",
+                    "port": 8443,
+                    "protocol": "HTTPS",
+                    "hostname": "whoami.magicloud.lan",
+                    "tls": {"mode": "Terminate", "certificateRefs": [{"name": "whoami"}]}
+                }
+            ]
+        }
+    })
+}
+
+pub fn plain_httproute() -> Value {
+    json!({
+        "apiVersion": "gateway.networking.k8s.io/v1",
+        "kind": "HTTPRoute",
+        "metadata": {"name": "whoami", "namespace": "test"},
+        "spec": {
+            "parentRefs": [{"kind": "Gateway", "name": "whoami", "sectionName": "http"}],
+            "rules": [{"backendRefs": [{"name": "whoami", "port": 80}]}]
+        }
+    })
+}
+
+pub async fn call(uri: &str, review: &Value, extra: &[&str]) -> Value {
+    call_in(
+        Cluster::from_objects(ClusterObjects::default()),
+        uri,
+        review,
+        extra,
+    )
+    .await
+}
+
+pub async fn call_in(cluster: Cluster, uri: &str, review: &Value, extra: &[&str]) -> Value {
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(SharedConf::fixed(cli(extra))))
+            .app_data(Data::new(cluster))
+            .service(post_validate)
+            .service(post_mutate),
+    )
+    .await;
+    let req = test::TestRequest::post()
+        .uri(uri)
+        .set_json(review)
+        .to_request();
+    test::call_and_read_body_json(&app, req).await
+}
+
+pub async fn mutate(object: &Value) -> Value {
+    call("/mutate", &review(object), &[]).await
+}
+
+// The object with the patch of the response applied.
+pub fn apply(object: &Value, response: &Value) -> Value {
+    assert_eq!(response["patchType"], "JSONPatch");
+    let patch: json_patch::Patch = serde_json::from_slice(
+        &serde_json::from_value::<Vec<u8>>(response["patch"].clone()).unwrap(),
+    )
+    .unwrap();
+    let mut target = object.clone();
+    json_patch::patch(&mut target, &patch).unwrap();
+    target
+}
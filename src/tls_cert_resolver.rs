@@ -10,6 +10,7 @@ use rustls::{
 };
 use smol::{Task, lock::RwLock, unblock};
 use tracing::instrument;
+use x509_parser::prelude::{FromDer, X509Certificate};
 
 // This is for Actix to hot-reload renewed TLS cert.
 // In this application's certain case, there is only one cert.
@@ -51,6 +52,16 @@ impl TLSCertResolver {
         Ok(self_)
     }
 
+    // Whether the serving certificate is within its validity period.
+    pub async fn is_certificate_valid(&self) -> bool {
+        let certified_key = self.certified_key.read().await.clone();
+        certified_key
+            .end_entity_cert()
+            .ok()
+            .and_then(|der| X509Certificate::from_der(der).ok())
+            .is_some_and(|(_, cert)| cert.validity().is_valid())
+    }
+
     #[instrument(skip_all)]
     fn watch(
         the_field: &Arc<RwLock<Arc<CertifiedKey>>>,
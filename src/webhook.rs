@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use actix_web::{
     App, HttpServer,
@@ -8,10 +8,15 @@ use actix_web::{
     post,
     web::{Data, Json},
 };
-use eyre::Result;
+use chrono::Utc;
+use eyre::{Report, Result, eyre};
 use kube::{
+    Client,
     api::DynamicObject,
-    core::admission::{AdmissionRequest, AdmissionResponse, AdmissionReview},
+    core::{
+        GroupVersionKind, TypeMeta,
+        admission::{AdmissionRequest, AdmissionResponse, AdmissionReview, Operation},
+    },
 };
 use rustls::ServerConfig;
 use serde_json::Value;
@@ -20,42 +25,103 @@ use tracing_actix_web::TracingLogger;
 
 #[allow(clippy::wildcard_imports)]
 use crate::{
-    cli::Cli, gateway::*, helpers::*, httproute::*, ingress::*, tls_cert_resolver::TLSCertResolver,
+    cli::{Cli, EnforcementMode, FailurePolicy},
+    cluster::Cluster,
+    controller::Auditor,
+    gateway::*,
+    helpers::*,
+    httproute::*,
+    ingress::*,
+    metrics,
+    policy::SharedConf,
+    probes::*,
+    tls_cert_resolver::TLSCertResolver,
 };
 
 impl Cli {
     pub async fn start(self) -> Result<()> {
-        let cert_solver = TLSCertResolver::new(
+        // Required by the arguments, unless running a subcommand.
+        let (Some(tls_folder), Some(certificate), Some(private_key)) = (
             &self.tls_folder,
             &self.tls_certificate_file_name,
             &self.tls_private_key_file_name,
+        ) else {
+            return Err(eyre!("The webhook needs -f, -c and -k"));
+        };
+        let cert_solver = TLSCertResolver::new(
+            tls_folder,
+            certificate,
+            private_key,
             rustls::crypto::CryptoProvider::get_default().expect("Provider did not initialize"),
         )
         .await?;
 
+        let cert_solver = Arc::new(cert_solver);
         let addr = self.listen_address.clone();
 
-        let data = Arc::new(self);
-        HttpServer::new(move || {
-            App::new()
-                .wrap(Logger::default())
-                .wrap(TracingLogger::default())
-                .app_data(Data::new(data.clone()))
-                .service(post_validate)
-                .service(post_mutate)
-        })
-        .workers(2)
-        // Adminssion Control webhooks are required to be secured.
-        // The services are accessed from within the cluster directly,
-        // so we cannot depend on Ingress to implement the TLS.
-        .bind_rustls_0_23(
-            addr,
-            ServerConfig::builder()
-                .with_no_client_auth()
-                .with_cert_resolver(Arc::new(cert_solver)),
-        )?
-        .run()
-        .await?;
+        let client = Client::try_default().await?;
+        let cluster = Cluster::new(client.clone()).await?;
+
+        // Probes are served in plain HTTP, from the start, so that liveness
+        // does not depend on the cache sync.
+        let probes = {
+            let cert_solver = cert_solver.clone();
+            let cluster = cluster.clone();
+            HttpServer::new(move || {
+                App::new()
+                    .app_data(Data::new(cert_solver.clone()))
+                    .app_data(Data::new(cluster.clone()))
+                    .service(get_healthz)
+                    .service(get_readyz)
+                    .service(get_metrics)
+            })
+            .workers(1)
+            .bind(self.probe_listen_address.clone())?
+            .run()
+        };
+
+        let audit_interval = self.audit_interval;
+        let data = SharedConf::watch(self)?;
+        let auditor = audit_interval.map(|interval| {
+            Auditor::new(data.clone(), cluster.clone(), client.clone(), interval).run(client)
+        });
+        let auditor = async move {
+            match auditor {
+                Some(auditor) => auditor.await,
+                None => Ok(()),
+            }
+        };
+        let webhook = async move {
+            // Answering before the cache is filled would judge against an empty cluster.
+            cluster.wait_until_synced().await?;
+            HttpServer::new(move || {
+                App::new()
+                    .wrap(Logger::default())
+                    .wrap(TracingLogger::default())
+                    .app_data(Data::new(data.clone()))
+                    .app_data(Data::new(cluster.clone()))
+                    .service(post_validate)
+                    .service(post_mutate)
+            })
+            .workers(2)
+            // Adminssion Control webhooks are required to be secured.
+            // The services are accessed from within the cluster directly,
+            // so we cannot depend on Ingress to implement the TLS.
+            .bind_rustls_0_23(
+                addr,
+                ServerConfig::builder()
+                    .with_no_client_auth()
+                    .with_cert_resolver(cert_solver),
+            )?
+            .run()
+            .await?;
+            Ok::<_, Report>(())
+        };
+        futures::try_join!(
+            async { probes.await.map_err(Report::from) },
+            webhook,
+            auditor
+        )?;
         Ok(())
     }
 }
@@ -66,16 +132,35 @@ fn json_guard(ctx: &GuardContext<'_>) -> bool {
 }
 
 #[post("/validate", guard = "json_guard")]
-async fn post_validate(admission_review: Json<Value>) -> Json<AdmissionReview<DynamicObject>> {
-    post_validate_(admission_review)
+pub async fn post_validate(
+    admission_review: Json<Value>,
+    conf: Data<SharedConf>,
+    cluster: Data<Cluster>,
+) -> Json<AdmissionReview<DynamicObject>> {
+    let start = Instant::now();
+    let json = admission_review.into_inner();
+    // The same configuration all along the request.
+    let conf = conf.current().await;
+    let policy = conf.failure_policy;
+    post_validate_(json.clone(), conf, cluster)
         .await
-        .map_or_else(|_| todo!(), |ret| Json(ret.into_review()))
+        .map_or_else(
+            |e| {
+                observe_error("validate", &json, start);
+                Json(error_response(&json, &e, policy).into_review())
+            },
+            |ret| Json(ret.into_review()),
+        )
 }
 
 #[instrument(skip_all)]
-async fn post_validate_(admission_review: Json<Value>) -> Result<AdmissionResponse> {
+async fn post_validate_(
+    json: Value,
+    conf: Arc<Cli>,
+    cluster: Data<Cluster>,
+) -> Result<AdmissionResponse> {
+    let start = Instant::now();
     let empty_string = String::new();
-    let json = admission_review.into_inner();
     let ar = serde_json::from_value::<AdmissionReview<DynamicObject>>(json)?;
     // One may use `ar.request` according to doc. But that is wrong. `try_into` is the proper way.
     // `request` returns a broken `AdmissionRequest`, such as no api version and kind data.
@@ -94,45 +179,80 @@ async fn post_validate_(admission_review: Json<Value>) -> Result<AdmissionRespon
         .and_then(|o| o.metadata.name.as_ref())
         .unwrap_or(&empty_string);
     tracing::info!(target: "validate", message = format!("Processing {} {}/{}", k, ns, n));
+    let requester = Requester::from(&req);
     let final_result = if let Some(obj) = req.object.clone() {
-        let dot = obj.types.as_ref().map(|t| &t.kind);
-        let ret = if dot.is_some_and(|x| x == "Ingress") {
-            let ingress = dynamic_object2ingress(obj)?;
-            validate_ingress().run(Arc::new(ingress)).await
-        } else if dot.is_some_and(|x| x == "Gateway") {
-            let gateway = dynamic_object2gateway(obj)?;
-            validate_gateway().run(Arc::new(gateway)).await
-        } else if dot.is_some_and(|x| x == "HTTPRoute") {
-            let httproute = dynamic_object2httproute(obj)?;
-            validate_httproute().run(Arc::new(httproute)).await
-        } else {
-            unimplemented!()
-        };
-        ret.into()
+        match SupportedKind::from_gvk(&req.kind) {
+            Some(SupportedKind::Ingress) => {
+                let ingress = dynamic_object2ingress(obj)?;
+                validate_ingress(&conf, &cluster, &requester)
+                    .run(Arc::new(ingress))
+                    .await
+                    .into()
+            }
+            Some(SupportedKind::Gateway) => {
+                let gateway = dynamic_object2gateway(obj)?;
+                validate_gateway(&conf, &cluster, &requester)
+                    .run(Arc::new(gateway))
+                    .await
+                    .into()
+            }
+            Some(SupportedKind::HTTPRoute) => {
+                let httproute = dynamic_object2httproute(obj)?;
+                validate_httproute(&conf, &cluster, &requester)
+                    .run(Arc::new(httproute))
+                    .await
+                    .into()
+            }
+            None => allow_unregistered("validate", &req.kind),
+        }
     } else {
         Status::Invalid("No object passed".to_string())
     };
     tracing::info!(target: "validate", message = format!("Result of {} {}/{}: {:?}", k, ns, n, final_result));
+    let mode = resolve_enforcement_mode(conf.enforcement_mode, ns, &cluster);
+    metrics::observe_admission(
+        "validate",
+        k,
+        operation_name(&req.operation),
+        &final_result,
+        mode,
+        start.elapsed(),
+    );
 
-    let x: StatusAdmissionResponse = (final_result, ret, (ns, n)).into();
-    Ok(x.into())
+    let mut ret = respond(final_result, ret, (ns, n), conf.failure_policy, mode);
+    if let Some(obj) = req.object.as_ref() {
+        flag_expired_skip(&mut ret, obj, k, (ns, n), &conf);
+    }
+    Ok(ret)
 }
 
 #[post("/mutate", guard = "json_guard")]
-async fn post_mutate(
+pub async fn post_mutate(
     admission_review: Json<Value>,
-    conf: Data<Arc<Cli>>,
+    conf: Data<SharedConf>,
+    cluster: Data<Cluster>,
 ) -> Json<AdmissionReview<DynamicObject>> {
-    (post_mutate_(admission_review, conf).await)
-        .map_or_else(|_| todo!(), |ret| Json(ret.into_review()))
+    let start = Instant::now();
+    let json = admission_review.into_inner();
+    // The same configuration all along the request.
+    let conf = conf.current().await;
+    let policy = conf.failure_policy;
+    post_mutate_(json.clone(), conf, cluster).await.map_or_else(
+        |e| {
+            observe_error("mutate", &json, start);
+            Json(error_response(&json, &e, policy).into_review())
+        },
+        |ret| Json(ret.into_review()),
+    )
 }
 
 async fn post_mutate_(
-    admission_review: Json<Value>,
-    conf: Data<Arc<Cli>>,
+    json: Value,
+    conf: Arc<Cli>,
+    cluster: Data<Cluster>,
 ) -> Result<AdmissionResponse> {
+    let start = Instant::now();
     let empty_string = String::new();
-    let json = admission_review.into_inner();
     let ar = serde_json::from_value::<AdmissionReview<DynamicObject>>(json)?;
     let req = ar.try_into()?;
     let ret = AdmissionResponse::from(&req);
@@ -148,26 +268,355 @@ async fn post_mutate_(
         .and_then(|o| o.metadata.name.as_ref())
         .unwrap_or(&empty_string);
     tracing::info!(target: "mutate", message = format!("Processing {} {}/{}", k, ns, n));
+    let requester = Requester::from(&req);
     let final_result = if let Some(obj) = req.object.clone() {
-        let dynamic_object_type = obj.types.as_ref().map(|t| &t.kind);
-        let ret = if dynamic_object_type.is_some_and(|x| x == "Ingress") {
-            let ingress = dynamic_object2ingress(obj)?;
-            mutate_ingress(Arc::new(ingress), &conf).await
-        } else if dynamic_object_type.is_some_and(|x| x == "Gateway") {
-            let gateway = dynamic_object2gateway(obj)?;
-            mutate_gateway(Arc::new(gateway), &conf).await
-        } else if dynamic_object_type.is_some_and(|x| x == "HTTPRoute") {
-            let httproute = dynamic_object2httproute(obj)?;
-            mutate_httproute(Arc::new(httproute)).await
-        } else {
-            unimplemented!()
-        };
-        ret.into()
+        match SupportedKind::from_gvk(&req.kind) {
+            Some(SupportedKind::Ingress) => {
+                let ingress = dynamic_object2ingress(obj)?;
+                mutate_ingress(Arc::new(ingress), &conf, &cluster, &requester)
+                    .await
+                    .into()
+            }
+            Some(SupportedKind::Gateway) => {
+                let gateway = dynamic_object2gateway(obj)?;
+                mutate_gateway(Arc::new(gateway), &conf, &cluster, &requester)
+                    .await
+                    .into()
+            }
+            Some(SupportedKind::HTTPRoute) => {
+                let httproute = dynamic_object2httproute(obj)?;
+                mutate_httproute(Arc::new(httproute), &conf, &cluster, &requester)
+                    .await
+                    .into()
+            }
+            None => allow_unregistered("mutate", &req.kind),
+        }
     } else {
         Status::Invalid("No object passed".to_string())
     };
     tracing::info!(target: "mutate", message = format!("Result of {} {}/{}: {:?}", k, ns, n, final_result));
+    let mode = resolve_enforcement_mode(conf.enforcement_mode, ns, &cluster);
+    metrics::observe_admission(
+        "mutate",
+        k,
+        operation_name(&req.operation),
+        &final_result,
+        mode,
+        start.elapsed(),
+    );
+
+    Ok(respond(
+        final_result,
+        ret,
+        (ns, n),
+        conf.failure_policy,
+        mode,
+    ))
+}
+
+// Such as `networking.istio.io/Gateway`, which only shares the kind name.
+fn allow_unregistered(target: &str, gvk: &GroupVersionKind) -> Status {
+    tracing::info!(
+        target: "dispatch",
+        message = format!(
+            "Allowing unregistered {}/{}/{} on {target}",
+            gvk.group, gvk.version, gvk.kind
+        )
+    );
+    Status::Allowed
+}
+
+// So that stale exemptions get chased.
+fn flag_expired_skip(
+    ret: &mut AdmissionResponse,
+    obj: &DynamicObject,
+    kind: &str,
+    (ns, n): (&String, &String),
+    conf: &Cli,
+) {
+    if let SkipState::Expired(until) = get_skip_state(obj, conf.skip_max_duration, Utc::now()) {
+        let until = until.to_rfc3339();
+        tracing::info!(target: "audit", message = format!("Expired skip on {kind} {ns}/{n}, since {until}"));
+        metrics::observe_expired_skip(kind);
+        ret.warnings.get_or_insert_default().push(format!(
+            "{ns}/{n}: the {SKIP_ANNOTATION} annotation expired at {until}, the checks apply again."
+        ));
+        ret.audit_annotations
+            .insert("expired-skip".to_string(), until);
+    }
+}
+
+// Internal errors are not the fault of the object. Failure policy decides.
+// Otherwise enforcement mode decides what a denial turns into.
+fn respond(
+    final_result: Status,
+    ret: AdmissionResponse,
+    (ns, n): (&String, &String),
+    policy: FailurePolicy,
+    mode: EnforcementMode,
+) -> AdmissionResponse {
+    match final_result {
+        Status::Denied(DenyReason::InternalError(e)) if policy == FailurePolicy::Open => {
+            let mut ret = ret;
+            ret.allowed = true;
+            ret.warnings = Some(vec![format!(
+                "{ns}/{n}: ingress-tls failed open. Internal Error occurred. {e}"
+            )]);
+            ret
+        }
+        Status::Denied(reason)
+            if mode == EnforcementMode::Warn && !matches!(reason, DenyReason::InternalError(_)) =>
+        {
+            let mut ret = ret;
+            ret.allowed = true;
+            ret.warnings = Some(vec![format!("{ns}/{n}: {reason}")]);
+            ret
+        }
+        Status::Denied(reason)
+            if mode == EnforcementMode::Audit
+                && !matches!(reason, DenyReason::InternalError(_)) =>
+        {
+            tracing::info!(target: "audit", message = format!("Would deny {ns}/{n}: {reason}"));
+            let mut ret = ret;
+            ret.allowed = true;
+            ret.audit_annotations
+                .insert("decision".to_string(), "denied".to_string());
+            ret.audit_annotations
+                .insert("reason".to_string(), reason.to_string());
+            ret
+        }
+        final_result => {
+            let x: StatusAdmissionResponse = (final_result, ret, (ns, n)).into();
+            x.into()
+        }
+    }
+}
+
+// As it appears in the AdmissionReview, e.g. `CREATE`.
+const fn operation_name(operation: &Operation) -> &'static str {
+    match operation {
+        Operation::Create => "CREATE",
+        Operation::Update => "UPDATE",
+        Operation::Delete => "DELETE",
+        Operation::Connect => "CONNECT",
+    }
+}
 
-    let x: StatusAdmissionResponse = (final_result, ret, (ns, n)).into();
-    Ok(x.into())
+fn observe_error(endpoint: &str, review: &Value, start: Instant) {
+    let field = |pointer| {
+        review
+            .pointer(pointer)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+    };
+    metrics::observe_admission_error(
+        endpoint,
+        field("/request/kind/kind"),
+        field("/request/operation"),
+        start.elapsed(),
+    );
+}
+
+// The request could not be processed at all, e.g. a malformed review.
+// Still reply a well-formed review, carrying the uid if there is one.
+fn error_response(review: &Value, e: &Report, policy: FailurePolicy) -> AdmissionResponse {
+    tracing::warn!(target: "internal-error", message = format!("{e:?}"));
+    let mut ret = AdmissionResponse::invalid(format!("Cannot process the admission request. {e}"));
+    ret.types = TypeMeta {
+        api_version: review
+            .get("apiVersion")
+            .and_then(Value::as_str)
+            .unwrap_or("admission.k8s.io/v1")
+            .to_string(),
+        kind: "AdmissionReview".to_string(),
+    };
+    ret.uid = review
+        .pointer("/request/uid")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    if policy == FailurePolicy::Open {
+        ret.allowed = true;
+        ret.warnings = Some(vec![format!(
+            "ingress-tls failed open. Cannot process the admission request. {e}"
+        )]);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        cluster::ClusterObjects,
+        testing::{call, call_in, ingress, review},
+    };
+
+    #[actix_web::test]
+    async fn malformed_review_fails_closed() {
+        let mut review = review(&ingress("nginx", None));
+        review["request"]["object"]["spec"] = json!("not a spec");
+        for uri in ["/validate", "/mutate"] {
+            let ret = call(uri, &review, &[]).await;
+            assert_eq!(ret["kind"], "AdmissionReview");
+            assert_eq!(
+                ret["response"]["uid"],
+                "705ab4f5-6393-11e8-b7cc-42010a800002"
+            );
+            assert_eq!(ret["response"]["allowed"], false);
+            assert_eq!(ret["response"]["status"]["reason"], "InvalidRequest");
+        }
+    }
+
+    #[actix_web::test]
+    async fn malformed_review_fails_open() {
+        for uri in ["/validate", "/mutate"] {
+            let ret = call(
+                uri,
+                &json!({"request": {"uid": "x"}}),
+                &["--failure-policy", "open"],
+            )
+            .await;
+            assert_eq!(ret["response"]["uid"], "x");
+            assert_eq!(ret["response"]["allowed"], true);
+            assert!(
+                ret["response"]["warnings"]
+                    .as_array()
+                    .is_some_and(|x| x.len() == 1)
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn unknown_kind_is_passed_through() {
+        let object = json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": {"name": "whoami", "namespace": "test"},
+            "data": {"a": "b"}
+        });
+        let review = review(&object);
+        for uri in ["/validate", "/mutate"] {
+            let ret = call(uri, &review, &[]).await;
+            assert_eq!(ret["response"]["allowed"], true);
+            assert!(ret["response"].get("patch").is_none());
+        }
+    }
+
+    #[actix_web::test]
+    async fn foreign_gateway_is_passed_through() {
+        let object = json!({
+            "apiVersion": "networking.istio.io/v1beta1",
+            "kind": "Gateway",
+            "metadata": {"name": "whoami", "namespace": "test"},
+            "spec": {
+                "selector": {"istio": "ingressgateway"},
+                "servers": [{
+                    "port": {"number": 80, "name": "http", "protocol": "HTTP"},
+                    "hosts": ["whoami.magicloud.lan"]
+                }]
+            }
+        });
+        for uri in ["/validate", "/mutate"] {
+            let ret = call(uri, &review(&object), &[]).await;
+            assert_eq!(ret["response"]["allowed"], true);
+            assert!(ret["response"].get("patch").is_none());
+        }
+    }
+
+    #[actix_web::test]
+    async fn served_versions_are_dispatched() {
+        for version in ["v1", "v1beta1"] {
+            let mut object = json!({
+                "apiVersion": format!("gateway.networking.k8s.io/{version}"),
+                "kind": "HTTPRoute",
+                "metadata": {"name": "whoami", "namespace": "test"},
+                "spec": {
+                    "rules": [{"backendRefs": [{"name": "whoami", "port": 80}]}]
+                }
+            });
+            let ret = call("/validate", &review(&object), &[]).await;
+            assert_eq!(ret["response"]["allowed"], true);
+
+            // Proves the object is parsed as a Gateway API HTTPRoute.
+            object["spec"] = json!({"rules": "not rules"});
+            let ret = call("/validate", &review(&object), &[]).await;
+            assert_eq!(ret["response"]["allowed"], false);
+        }
+    }
+
+    #[actix_web::test]
+    async fn denials_are_counted() {
+        call("/validate", &review(&ingress("nginx", None)), &[]).await;
+        let gathered = metrics::gather().unwrap();
+        assert!(gathered.contains(
+            r#"ingress_tls_deny_reasons_total{endpoint="validate",reason="IngressNoTLS"}"#
+        ));
+        assert!(gathered.contains(
+            r#"ingress_tls_admission_requests_total{endpoint="validate",kind="Ingress",operation="CREATE",outcome="denied"}"#
+        ));
+    }
+
+    fn labeled_namespace(mode: &str) -> Cluster {
+        let namespace = serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "Namespace",
+            "metadata": {"name": "test", "labels": {ENFORCE_LABEL: mode}}
+        }))
+        .unwrap();
+        Cluster::from_objects(ClusterObjects {
+            namespaces: vec![namespace],
+            ..Default::default()
+        })
+    }
+
+    #[actix_web::test]
+    async fn warn_mode_allows_with_warning() {
+        let object = ingress("nginx", None);
+        let ret = call(
+            "/validate",
+            &review(&object),
+            &["--enforcement-mode", "warn"],
+        )
+        .await;
+        assert_eq!(ret["response"]["allowed"], true);
+        let warning = ret["response"]["warnings"][0].as_str().unwrap();
+        assert!(warning.contains("does not contain a TLS configuration"));
+    }
+
+    #[actix_web::test]
+    async fn namespace_label_overrides_enforcement_mode() {
+        let object = ingress("nginx", None);
+        let ret = call_in(
+            labeled_namespace("audit"),
+            "/validate",
+            &review(&object),
+            &[],
+        )
+        .await;
+        assert_eq!(ret["response"]["allowed"], true);
+        assert_eq!(ret["response"]["auditAnnotations"]["decision"], "denied");
+        assert!(ret["response"]["warnings"].is_null());
+
+        let ret = call_in(
+            labeled_namespace("enforce"),
+            "/validate",
+            &review(&object),
+            &["--enforcement-mode", "warn"],
+        )
+        .await;
+        assert_eq!(ret["response"]["allowed"], false);
+
+        // Unknown values fall back to the global default.
+        let ret = call_in(
+            labeled_namespace("dry-run"),
+            "/validate",
+            &review(&object),
+            &[],
+        )
+        .await;
+        assert_eq!(ret["response"]["allowed"], false);
+    }
 }
@@ -177,6 +177,7 @@ fn skip_gateway() {
                         "whoami.magicloud.lan.",
                     ),
                     ("ingress-tls.magiclouds.cn/skip", "true"),
+                    ("ingress-tls.magiclouds.cn/skip-reason", "Testing"),
                 ]
                 .into_iter()
                 .map(|(x, y)| (x.to_string(), y.to_string())),
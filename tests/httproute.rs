@@ -152,9 +152,12 @@ fn skip_httproute() {
         metadata: ObjectMeta {
             name: Some(gen_name("bad-http")),
             annotations: Some(BTreeMap::from_iter(
-                [("ingress-tls.magiclouds.cn/skip", "true")]
-                    .into_iter()
-                    .map(|(x, y)| (x.to_string(), y.to_string())),
+                [
+                    ("ingress-tls.magiclouds.cn/skip", "true"),
+                    ("ingress-tls.magiclouds.cn/skip-reason", "Testing"),
+                ]
+                .into_iter()
+                .map(|(x, y)| (x.to_string(), y.to_string())),
             )),
             ..Default::default()
         },
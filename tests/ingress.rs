@@ -115,6 +115,7 @@ fn skip_ingress() {
                         "whoami.magicloud.lan.",
                     ),
                     ("ingress-tls.magiclouds.cn/skip", "true"),
+                    ("ingress-tls.magiclouds.cn/skip-reason", "Testing"),
                 ]
                 .into_iter()
                 .map(|(x, y)| (x.to_string(), y.to_string())),
@@ -149,3 +150,49 @@ fn skip_ingress() {
     };
     assert!(run(skip_ingress, vec![] as Vec<Ingress>).is_ok())
 }
+
+#[test]
+fn partial_tls_ingress() {
+    let partial_tls_ingress = Ingress {
+        metadata: ObjectMeta {
+            annotations: Some(BTreeMap::from_iter(
+                [(
+                    "external-dns.alpha.kubernetes.io/hostname",
+                    "whoami.magicloud.lan.,whoami2.magicloud.lan.",
+                )]
+                .into_iter()
+                .map(|(x, y)| (x.to_string(), y.to_string())),
+            )),
+            name: Some(gen_name("partial-tls")),
+            ..ObjectMeta::default()
+        },
+        spec: Some(IngressSpec {
+            rules: Some(vec![IngressRule {
+                host: Some("whoami.magicloud.lan".to_string()),
+                http: Some(HTTPIngressRuleValue {
+                    paths: vec![HTTPIngressPath {
+                        backend: IngressBackend {
+                            resource: None,
+                            service: Some(IngressServiceBackend {
+                                name: "whoami".to_string(),
+                                port: Some(ServiceBackendPort {
+                                    name: None,
+                                    number: Some(80),
+                                }),
+                            }),
+                        },
+                        path: Some("/".to_string()),
+                        path_type: "Prefix".to_string(),
+                    }],
+                }),
+            }]),
+            tls: Some(vec![IngressTLS {
+                hosts: Some(vec!["whoami.magicloud.lan".to_string()]),
+                secret_name: Some("partial-tls-ingress-tls".to_string()),
+            }]),
+            ..IngressSpec::default()
+        }),
+        status: None,
+    };
+    assert!(run(partial_tls_ingress, vec![] as Vec<Ingress>).is_err())
+}